@@ -0,0 +1,81 @@
+use std::ffi::{c_char, c_void};
+use crate::c::net::RUNTIME;
+use crate::c::util::{cstr_to_rust, ngenrs_free_ptr, box_into_raw_new, set_last_error, clear_last_error};
+use crate::core::engine::Engine;
+
+pub const NGENRS_ERR_ENGINE: i32 = 301;
+
+/// `kv_path` may be null to use the default database location. The engine shares
+/// the runtime of the other `ngenrs_*` functions. Returns null, with the reason in
+/// the last error, when the store cannot be opened or the engine not set up.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_engine_init(kv_path: *const c_char) -> *mut c_void {
+    let mut builder = Engine::builder().runtime(RUNTIME.handle().clone());
+    if let Some(path) = cstr_to_rust(kv_path) {
+        builder = builder.kv_path(path);
+    }
+    match builder.build() {
+        Ok(engine) => {
+            clear_last_error();
+            box_into_raw_new(engine) as *mut c_void
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_ENGINE, e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_engine_release(engine: *mut c_void) {
+    ngenrs_free_ptr(engine as *mut Engine)
+}
+
+/// Borrowed bridge for the `ngenrs_lua_*` functions; valid until the engine is
+/// released and must not be passed to `ngenrs_lua_bridge_release`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_engine_lua_bridge(engine: *mut c_void) -> *mut c_void {
+    if engine.is_null() {
+        return std::ptr::null_mut();
+    }
+    let engine = unsafe { &*(engine as *mut Engine) };
+    engine.lua() as *const _ as *mut c_void
+}
+
+/// Borrowed store for the `ngenrs_kv_*` functions; valid until the engine is
+/// released and must not be passed to `ngenrs_kv_close`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_engine_kv(engine: *mut c_void) -> *mut c_void {
+    if engine.is_null() {
+        return std::ptr::null_mut();
+    }
+    let engine = unsafe { &*(engine as *mut Engine) };
+    engine.kv().as_ref() as *const _ as *mut c_void
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use crate::c::util::{ngenrs_last_error_code, NGENRS_ERR_NONE};
+    use crate::test_util::TempPath;
+
+    #[test]
+    fn init_reports_why_the_engine_could_not_be_built() {
+        let dir = TempPath::new("engine-dir");
+        std::fs::create_dir(&*dir).unwrap();
+        let in_missing_dir = CString::new(dir.join("missing").join("engine.redb").to_str().unwrap()).unwrap();
+        assert!(ngenrs_engine_init(in_missing_dir.as_ptr()).is_null());
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_ENGINE);
+
+        let path = CString::new(dir.join("engine.redb").to_str().unwrap()).unwrap();
+        let engine = ngenrs_engine_init(path.as_ptr());
+        assert!(!engine.is_null());
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_NONE);
+        ngenrs_engine_release(engine);
+    }
+}
@@ -1,6 +1,7 @@
 use std::os::raw::{c_char, c_void};
 use crate::c::util::{cstr_to_rust, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new};
 use crate::core::kv::KV;
+use serde_json::json;
 
 #[unsafe(no_mangle)]
 pub extern "C" 
@@ -125,8 +126,34 @@ fn ngenrs_kv_read_string(store: *mut c_void, key: *const c_char) -> *mut c_char
     }
 }
 
+/// Returns a JSON object such as
+/// `{"schema_version":1,"file_size":4096,"tables":[{"name":"strings","entries":3,"stored_bytes":42}]}`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_kv_describe(store: *mut c_void) -> *mut c_char {
+    if store.is_null() { return std::ptr::null_mut(); }
+    let kv_ref = unsafe { &*(store as *mut KV) };
+    let info = match kv_ref.describe() {
+        Ok(info) => info,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let tables = info.tables.iter()
+        .map(|table| json!({
+            "name": table.name,
+            "entries": table.entries,
+            "stored_bytes": table.stored_bytes,
+        }))
+        .collect::<Vec<_>>();
+    let json = json!({
+        "schema_version": info.schema_version,
+        "file_size": info.file_size,
+        "tables": tables,
+    });
+    rust_to_cstr(json.to_string())
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_close(store: *mut c_void) {
-    ngenrs_free_ptr(store)
+    ngenrs_free_ptr(store as *mut KV)
 }
\ No newline at end of file
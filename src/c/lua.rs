@@ -1,20 +1,52 @@
 use std::ffi::{c_char, c_void};
-use crate::c::util::{cstr_to_rust, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new};
-use crate::core::lua::LuaBridge;
+use crate::c::util::{
+    cstr_to_rust, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new,
+    set_last_error, clear_last_error, NGENRS_ERR_INVALID_ARG,
+};
+use crate::core::lua::{LuaBridge, LuaError, LuaErrorKind};
+
+pub const NGENRS_ERR_LUA_SYNTAX: i32 = 101;
+pub const NGENRS_ERR_LUA_RUNTIME: i32 = 102;
+pub const NGENRS_ERR_LUA_MEMORY: i32 = 103;
+pub const NGENRS_ERR_LUA_OTHER: i32 = 199;
+
+fn set_lua_error(e: &LuaError) {
+    let code = match e.kind {
+        LuaErrorKind::Syntax => NGENRS_ERR_LUA_SYNTAX,
+        LuaErrorKind::Runtime => NGENRS_ERR_LUA_RUNTIME,
+        LuaErrorKind::Memory => NGENRS_ERR_LUA_MEMORY,
+        LuaErrorKind::Other => NGENRS_ERR_LUA_OTHER,
+    };
+    set_last_error(code, e.message.clone());
+}
+
+fn lua_result<T>(result: Result<T, LuaError>) -> Result<T, LuaError> {
+    match &result {
+        Ok(_) => clear_last_error(),
+        Err(e) => set_lua_error(e),
+    }
+    result
+}
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_lua_bridge_init() -> *mut c_void {
     match LuaBridge::new() {
-        Ok(bridge) => box_into_raw_new(bridge) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
+        Ok(bridge) => {
+            clear_last_error();
+            box_into_raw_new(bridge) as *mut c_void
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_LUA_OTHER, e);
+            std::ptr::null_mut()
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_lua_bridge_release(bridge: *mut c_void) {
-    ngenrs_free_ptr(bridge)
+    ngenrs_free_ptr(bridge as *mut LuaBridge)
 }
 
 #[unsafe(no_mangle)]
@@ -24,14 +56,18 @@ fn ngenrs_lua_load_file(
     path: *const c_char,
 ) -> bool {
     if bridge.is_null() || path.is_null() {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "bridge and path must not be null");
         return false;
     }
     let bridge = unsafe { &*(bridge as *mut LuaBridge) };
     let path_str = match cstr_to_rust(path) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_last_error(NGENRS_ERR_INVALID_ARG, "path is not valid UTF-8");
+            return false;
+        }
     };
-    bridge.load_file(&path_str).is_ok()
+    lua_result(bridge.load_file(&path_str)).is_ok()
 }
 
 #[unsafe(no_mangle)]
@@ -41,14 +77,18 @@ fn ngenrs_lua_load_string(
     script: *const c_char,
 ) -> bool {
     if bridge.is_null() || script.is_null() {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "bridge and script must not be null");
         return false;
     }
     let bridge = unsafe { &*(bridge as *mut LuaBridge) };
     let script_str = match cstr_to_rust(script) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_last_error(NGENRS_ERR_INVALID_ARG, "script is not valid UTF-8");
+            return false;
+        }
     };
-    bridge.load_string(&script_str).is_ok()
+    lua_result(bridge.load_string(&script_str)).is_ok()
 }
 
 #[unsafe(no_mangle)]
@@ -61,21 +101,28 @@ fn ngenrs_lua_call_function(
     err_out: *mut *mut c_char,
 ) -> bool {
     if bridge.is_null() || func_name.is_null() {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "bridge and func_name must not be null");
         return false;
     }
 
     let bridge = unsafe { &*(bridge as *mut LuaBridge) };
     let func_name_str = match cstr_to_rust(func_name) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_last_error(NGENRS_ERR_INVALID_ARG, "func_name is not valid UTF-8");
+            return false;
+        }
     };
 
     let arg_str = match cstr_to_rust(arg) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_last_error(NGENRS_ERR_INVALID_ARG, "arg must be a valid UTF-8 string");
+            return false;
+        }
     };
 
-    match bridge.call_function(func_name_str, arg_str) {
+    match lua_result(bridge.call_function(func_name_str, arg_str)) {
         Ok(result) => {
             if !result_out.is_null() {
                 unsafe { *result_out = rust_to_cstr(result) };
@@ -89,4 +136,29 @@ fn ngenrs_lua_call_function(
             false
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use crate::c::util::{ngenrs_last_error_code, NGENRS_ERR_NONE};
+
+    #[test]
+    fn syntax_and_runtime_errors_have_distinct_codes() {
+        let bridge = ngenrs_lua_bridge_init();
+        assert!(!bridge.is_null());
+
+        let script = CString::new("x = = 1").unwrap();
+        assert!(!ngenrs_lua_load_string(bridge, script.as_ptr()));
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_LUA_SYNTAX);
+
+        let script = CString::new("error('boom')").unwrap();
+        assert!(!ngenrs_lua_load_string(bridge, script.as_ptr()));
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_LUA_RUNTIME);
+
+        let script = CString::new("function echo(s) return s end").unwrap();
+        assert!(ngenrs_lua_load_string(bridge, script.as_ptr()));
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_NONE);
+    }
+}
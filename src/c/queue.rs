@@ -0,0 +1,75 @@
+use std::os::raw::{c_char, c_void};
+use crate::c::net::{set_request_error, RUNTIME};
+use crate::c::util::{cstr_to_rust, rust_map_from_c_arrays, clear_last_error};
+use crate::core::kv::KV;
+use crate::core::net::{HttpClient, Method};
+use crate::core::queue::RequestQueue;
+
+/// Appends a request to the queue `name` stored in `store`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_request_queue_enqueue(
+    store: *mut c_void,
+    name: *const c_char,
+    method: *const c_char,
+    url: *const c_char,
+    header_keys: *const *const c_char,
+    header_values: *const *const c_char,
+    headers_len: usize,
+    body: *const c_char,
+) -> bool {
+    if store.is_null() {
+        return false;
+    }
+    let kv = unsafe { &*(store as *const KV) };
+    let (name, url) = match (cstr_to_rust(name), cstr_to_rust(url)) {
+        (Some(name), Some(url)) => (name, url),
+        _ => return false,
+    };
+    let method = match cstr_to_rust(method).and_then(|m| Method::from_bytes(m.as_bytes()).ok()) {
+        Some(method) => method,
+        None => return false,
+    };
+    let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
+    let body = cstr_to_rust(body);
+
+    RequestQueue::new(kv, name).enqueue(method, url, headers, body).is_ok()
+}
+
+/// Replays the queue `name` through `client`. Returns the number of requests
+/// delivered, or -1 on invalid arguments. A flush that stops at a failed request
+/// still returns the count delivered before it, and sets the last error to why it
+/// stopped; the last error is cleared when the queue was emptied.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_request_queue_flush(store: *mut c_void, name: *const c_char, client: *const c_void) -> i64 {
+    if store.is_null() || client.is_null() {
+        return -1;
+    }
+    let kv = unsafe { &*(store as *const KV) };
+    let client = unsafe { &*(client as *const HttpClient) };
+    let name = match cstr_to_rust(name) {
+        Some(name) => name,
+        None => return -1,
+    };
+
+    let report = RUNTIME.block_on(RequestQueue::new(kv, name).flush(client));
+    match &report.error {
+        Some(e) => set_request_error(&**e),
+        None => clear_last_error(),
+    }
+    report.delivered as i64
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_request_queue_len(store: *mut c_void, name: *const c_char) -> i64 {
+    if store.is_null() {
+        return -1;
+    }
+    let kv = unsafe { &*(store as *const KV) };
+    match cstr_to_rust(name).map(|name| RequestQueue::new(kv, name).len()) {
+        Some(Ok(len)) => len as i64,
+        _ => -1,
+    }
+}
@@ -0,0 +1,114 @@
+use std::os::raw::{c_char, c_void};
+use std::time::Duration;
+use crate::c::util::{cstr_to_rust, rust_to_cstr, set_last_error, clear_last_error, NGENRS_ERR_INVALID_ARG};
+use crate::core::kv::KV;
+use crate::core::session::SessionStore;
+
+pub const NGENRS_ERR_SESSION: i32 = 501;
+
+/// Creates a session in `store` holding `data`, expiring after `ttl_ms` without
+/// access. Returns the new session ID, freed with `ngenrs_free_cstr`, or null on error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_session_create(store: *mut c_void, ttl_ms: u64, data: *const c_char) -> *mut c_char {
+    let (false, Some(data)) = (store.is_null(), cstr_to_rust(data)) else {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "store or data is null");
+        return std::ptr::null_mut();
+    };
+    let kv = unsafe { &*(store as *const KV) };
+
+    match SessionStore::new(kv, Duration::from_millis(ttl_ms)).create(data) {
+        Ok(session_id) => {
+            clear_last_error();
+            rust_to_cstr(session_id)
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_SESSION, e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the session's data and extends its life by `ttl_ms`, or null if it
+/// does not exist or expired, which `ngenrs_last_error_code` tells apart from an error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_session_get(store: *mut c_void, ttl_ms: u64, session_id: *const c_char) -> *mut c_char {
+    let (false, Some(session_id)) = (store.is_null(), cstr_to_rust(session_id)) else {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "store or session_id is null");
+        return std::ptr::null_mut();
+    };
+    let kv = unsafe { &*(store as *const KV) };
+
+    match SessionStore::new(kv, Duration::from_millis(ttl_ms)).get(session_id) {
+        Ok(data) => {
+            clear_last_error();
+            data.map_or(std::ptr::null_mut(), rust_to_cstr)
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_SESSION, e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Returns whether a live session was destroyed; false is an error only if
+/// `ngenrs_last_error_code` says so.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_session_destroy(store: *mut c_void, session_id: *const c_char) -> bool {
+    let (false, Some(session_id)) = (store.is_null(), cstr_to_rust(session_id)) else {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "store or session_id is null");
+        return false;
+    };
+    let kv = unsafe { &*(store as *const KV) };
+
+    // Destroying does not touch the TTL
+    match SessionStore::new(kv, Duration::ZERO).destroy(session_id) {
+        Ok(destroyed) => {
+            clear_last_error();
+            destroyed
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_SESSION, e.to_string());
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use crate::c::util::{ngenrs_free_cstr, ngenrs_last_error_code, NGENRS_ERR_NONE};
+    use crate::test_util::temp_kv;
+
+    #[test]
+    fn failures_set_the_last_error_and_misses_do_not() {
+        let (kv, _path) = temp_kv();
+        let store = &kv as *const KV as *mut c_void;
+        let data = CString::new("alice").unwrap();
+        let null = std::ptr::null();
+
+        assert!(ngenrs_session_create(std::ptr::null_mut(), 1000, data.as_ptr()).is_null());
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_INVALID_ARG);
+        assert!(ngenrs_session_get(store, 1000, null).is_null());
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_INVALID_ARG);
+        assert!(!ngenrs_session_destroy(store, null));
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_INVALID_ARG);
+
+        let id = ngenrs_session_create(store, 60_000, data.as_ptr());
+        assert!(!id.is_null());
+        let fetched = ngenrs_session_get(store, 60_000, id);
+        assert_eq!(cstr_to_rust(fetched), Some("alice"));
+        ngenrs_free_cstr(fetched);
+        assert!(ngenrs_session_destroy(store, id));
+
+        // A missing session is not an error
+        assert!(ngenrs_session_get(store, 60_000, id).is_null());
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_NONE);
+        assert!(!ngenrs_session_destroy(store, id));
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_NONE);
+        ngenrs_free_cstr(id);
+    }
+}
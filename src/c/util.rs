@@ -1,9 +1,87 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::sync::atomic::{AtomicBool, Ordering};
 use libc;
 use std::slice;
 
+/// No error recorded on this thread
+pub const NGENRS_ERR_NONE: i32 = 0;
+/// A required argument was null or not valid UTF-8
+pub const NGENRS_ERR_INVALID_ARG: i32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(i32, String)>> = const { RefCell::new(None) };
+}
+
+/// Records the failure of the current FFI call for `ngenrs_last_error_*`
+pub fn set_last_error(code: i32, message: impl Into<String>) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some((code, message.into())));
+}
+
+pub fn clear_last_error() {
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+}
+
+/// Code of the last failed FFI call on this thread, or `NGENRS_ERR_NONE`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_last_error_code() -> i32 {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(NGENRS_ERR_NONE, |(code, _)| *code))
+}
+
+/// Message of the last failed FFI call on this thread (free with `ngenrs_free_cstr`), or null
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|e| match e.borrow().as_ref() {
+        Some((_, message)) => rust_to_cstr(message.clone()),
+        None => std::ptr::null_mut(),
+    })
+}
+
+// Header names handed out as shared pointers while interning is enabled. Being
+// static, they are never freed and `ngenrs_free_cstr` tells them apart by address
+static INTERNABLE_HEADER_NAMES: [&CStr; 16] = [
+    c"accept-ranges", c"age", c"cache-control", c"connection", c"content-encoding",
+    c"content-length", c"content-type", c"date", c"etag", c"expires", c"last-modified",
+    c"location", c"server", c"set-cookie", c"transfer-encoding", c"vary",
+];
+
+static INTERN_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Returns a process-lifetime pointer for a common header name, or null if `name`
+/// is not internable or interning is disabled
+fn intern_header_name(name: &str) -> *mut c_char {
+    if !INTERN_ENABLED.load(Ordering::Relaxed) {
+        return std::ptr::null_mut();
+    }
+    INTERNABLE_HEADER_NAMES.iter()
+        .find(|s| s.to_bytes() == name.as_bytes())
+        .map_or(std::ptr::null_mut(), |s| s.as_ptr() as *mut c_char)
+}
+
+fn is_interned(ptr: *const c_char) -> bool {
+    INTERNABLE_HEADER_NAMES.iter().any(|s| s.as_ptr() == ptr)
+}
+
+/// Makes header-name outputs (e.g. of `ngenrs_http_parse_rsp_headers`) return
+/// shared pointers for common names instead of allocating per call. They are
+/// static strings, valid for the life of the process; passing them to
+/// `ngenrs_free_cstr` is a no-op.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_intern_set_enabled(enabled: bool) {
+    INTERN_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Releases nothing, as interned names are static: pointers returned before stay
+/// valid, also once interning is disabled.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_intern_clear() {}
+
 /// Utility function to convert C string to Rust string (safe wrapper)
 pub fn cstr_to_rust(cstr: *const c_char) -> Option<&'static str> {
     if cstr.is_null() {
@@ -61,13 +139,18 @@ pub fn ngenrs_free_ptr<T>(raw: *mut T) {
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_free_cstr(s: *mut c_char) {
+    if s.is_null() || is_interned(s) {
+        return;
+    }
     free(unsafe { CString::from_raw(s) });
 }
 
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_free_bytes(buf: *mut u8, len: usize) {
-    ngenrs_free_ptr(unsafe { slice::from_raw_parts_mut(buf, len).as_mut_ptr() });
+    if !buf.is_null() {
+        free(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(buf, len)) });
+    }
 }
 
 pub fn box_into_raw_new<T>(value: T) -> *mut T {
@@ -79,58 +162,131 @@ pub unsafe fn rust_map_from_c_arrays(
     values: *const *const c_char,
     len: usize,
 ) -> Option<HashMap<String, String>> {
+    unsafe { rust_pairs_from_c_arrays(keys, values, len) }.map(|pairs| pairs.into_iter().collect())
+}
+
+/// Like `rust_map_from_c_arrays`, but keeps the order and any repeated keys.
+///
+/// # Safety
+/// Non-null `keys` and `values` must each point to `len` pointers, every one null
+/// or a valid NUL-terminated string; pairs with a null key or value are skipped.
+pub unsafe fn rust_pairs_from_c_arrays(
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    len: usize,
+) -> Option<Vec<(String, String)>> {
     if keys.is_null() || values.is_null() {
         return None;
     }
-    let mut map = HashMap::new();
+    let mut pairs = Vec::with_capacity(len);
     let keys_slice = unsafe { std::slice::from_raw_parts(keys, len) };
     let values_slice = unsafe { std::slice::from_raw_parts(values, len) };
     
     for i in 0..len {
         if let (Some(key), Some(value)) = (cstr_to_rust(keys_slice[i]), cstr_to_rust(values_slice[i])) {
-            map.insert(key.to_string(), value.to_string());
+            pairs.push((key.to_string(), value.to_string()));
         }
     }
-    Some(map)
+    Some(pairs)
 }
 
-/// Converts Rust HashMap to C-style string arrays
-/// Returns tuple of (keys_ptr, values_ptr, len)
+/// Copies `map` into caller-allocated C string arrays in two phases:
+/// 1. Call with null `keys_out`/`values_out`: `*count_inout` receives `map.len()`.
+/// 2. Allocate arrays of that many pointers, set `*count_inout` to their capacity
+///    and call again: at most that many pairs are written and `*count_inout`
+///    receives the number actually written.
+///
+/// Each written string must be freed with `ngenrs_free_cstr`.
+///
+/// # Safety
+/// `count_inout` must be null or valid for reads and writes, and non-null
+/// `keys_out`/`values_out` must each have room for `*count_inout` pointers.
 pub unsafe fn rust_map_to_c_arrays(
     map: &HashMap<String, String>,
     keys_out: *mut *mut c_char,
     values_out: *mut *mut c_char,
-    count_out: *mut usize
+    count_inout: *mut usize
+) {
+    let pairs: Vec<_> = map.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    unsafe { rust_pairs_to_c_arrays(&pairs, keys_out, values_out, count_inout) }
+}
+
+/// Like `rust_map_to_c_arrays`, but keeps the order and any repeated keys.
+///
+/// # Safety
+/// As for `rust_map_to_c_arrays`.
+pub unsafe fn rust_pairs_to_c_arrays(
+    pairs: &[(&str, &str)],
+    keys_out: *mut *mut c_char,
+    values_out: *mut *mut c_char,
+    count_inout: *mut usize
 ) {
-    if keys_out.is_null() || values_out.is_null() || count_out.is_null() {
+    if count_inout.is_null() {
         return;
     }
 
-    let len = map.len();
-    unsafe { *count_out = len };
-
-    if len == 0 {
+    if keys_out.is_null() || values_out.is_null() {
+        unsafe { *count_inout = pairs.len() };
         return;
     }
 
-    // Allocate arrays for keys and values
-    let mut keys_vec: Vec<*mut c_char> = Vec::with_capacity(len);
-    let mut values_vec: Vec<*mut c_char> = Vec::with_capacity(len);
+    let capacity = unsafe { *count_inout };
+    let mut written = 0;
+    for &(k, v) in pairs.iter().take(capacity) {
+        let key = intern_header_name(k);
+        let key = if key.is_null() { rust_to_cstr(k.to_string()) } else { key };
+        unsafe {
+            *keys_out.add(written) = key;
+            *values_out.add(written) = rust_to_cstr(v.to_string());
+        }
+        written += 1;
+    }
+    unsafe { *count_inout = written };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_header_names_share_a_pointer() {
+        let map = HashMap::from([("content-type".to_string(), "text/plain".to_string())]);
+        let read_key = || {
+            let (mut key, mut value, mut count) = (std::ptr::null_mut(), std::ptr::null_mut(), 1);
+            unsafe { rust_map_to_c_arrays(&map, &mut key, &mut value, &mut count) };
+            ngenrs_free_cstr(value);
+            key
+        };
 
-    for (k, v) in map {
-        keys_vec.push(rust_to_cstr(k.clone()));
-        values_vec.push(rust_to_cstr(v.clone()));
+        ngenrs_intern_set_enabled(true);
+        let first = read_key();
+        let second = read_key();
+        assert_eq!(first, second);
+
+        // Earlier pointers survive a clear, which leaves nothing to intern afresh,
+        // and freeing them does nothing
+        ngenrs_intern_clear();
+        assert_eq!(read_key(), first);
+        ngenrs_free_cstr(first);
+        ngenrs_free_cstr(second);
+        assert_eq!(cstr_to_rust(first), Some("content-type"));
+        ngenrs_intern_set_enabled(false);
+
+        let owned = read_key();
+        assert_ne!(owned, first);
+        assert!(!is_interned(owned));
+        ngenrs_free_cstr(owned);
     }
 
-    let keys_ptr = Box::into_raw(keys_vec.into_boxed_slice()) as *mut *mut c_char;
-    let values_ptr = Box::into_raw(values_vec.into_boxed_slice()) as *mut *mut c_char;
-    
-    // Copy to output pointers
-    unsafe {
-        std::ptr::copy_nonoverlapping(keys_ptr, keys_out, len);
-        std::ptr::copy_nonoverlapping(values_ptr, values_out, len);
-        
-        ngenrs_free_ptr(keys_ptr);
-        ngenrs_free_ptr(values_ptr);
+    #[test]
+    fn freed_bytes_give_back_the_whole_buffer() {
+        let before = crate::test_util::live_bytes();
+        let (buf, len) = rust_to_cbytes(vec![7; 4096]);
+        assert_eq!(len, 4096);
+        assert_eq!(crate::test_util::live_bytes() - before, 4096);
+        ngenrs_free_bytes(buf, len);
+        assert_eq!(crate::test_util::live_bytes(), before);
+
+        ngenrs_free_bytes(std::ptr::null_mut(), 0);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,302 @@
+use std::cell::RefCell;
+use std::os::raw::{c_char, c_void};
+use std::time::Duration;
+use crate::c::net::RUNTIME;
+use crate::c::util::{box_into_raw_new, cbytes_to_rust, clear_last_error, cstr_to_rust, ngenrs_free_ptr, rust_map_from_c_arrays, set_last_error, NGENRS_ERR_INVALID_ARG};
+use crate::core::net::RetryPolicy;
+use crate::core::ws::{WsClient, WsMessage, WsOfflineSends, WsReconnect};
+
+pub const NGENRS_ERR_WS: i32 = 401;
+
+pub const NGENRS_WS_TEXT: i32 = 1;
+pub const NGENRS_WS_BINARY: i32 = 2;
+pub const NGENRS_WS_CLOSE: i32 = 8;
+
+/// Receives a message's kind (`NGENRS_WS_TEXT`, `NGENRS_WS_BINARY` or
+/// `NGENRS_WS_CLOSE`), its payload and the caller's `userdata`. Text is UTF-8 and
+/// not NUL-terminated. A close payload is laid out as on the wire: the close code
+/// as two big-endian bytes followed by the reason, or empty when the server gave none.
+pub type NgenrsWsMessageCallback = extern "C" fn(kind: i32, data: *const u8, len: usize, userdata: *mut c_void);
+
+/// Called with the caller's `userdata` each time an auto-reconnecting connection
+/// is reopened. Messages it sends on that connection with `ngenrs_ws_send_text` or
+/// `ngenrs_ws_send_binary`, such as subscriptions, go out before any queued ones.
+pub type NgenrsWsReconnectCallback = extern "C" fn(userdata: *mut c_void);
+
+thread_local! {
+    // What the running `on_reconnect` callback sent, and on which connection
+    static RECONNECT_OUTBOX: RefCell<Option<(usize, Vec<WsMessage>)>> = const { RefCell::new(None) };
+}
+
+// The runtime is busy reconnecting while `on_reconnect` runs, so sends on that
+// connection are collected for the hook to return instead of blocking on it
+fn collect_for_reconnect(ws: &WsClient, message: impl FnOnce() -> WsMessage) -> bool {
+    RECONNECT_OUTBOX.with_borrow_mut(|outbox| match outbox {
+        Some((owner, messages)) if *owner == ws as *const WsClient as usize => {
+            messages.push(message());
+            true
+        }
+        _ => false,
+    })
+}
+
+fn ws_result(result: Result<(), Box<dyn std::error::Error>>) -> bool {
+    match result {
+        Ok(()) => {
+            clear_last_error();
+            true
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_WS, e.to_string());
+            false
+        }
+    }
+}
+
+/// Returns a handle for the other `ngenrs_ws_*` functions, released with
+/// `ngenrs_ws_release`, or null if the connection failed.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_connect(
+    url: *const c_char,
+    header_keys: *const *const c_char,
+    header_values: *const *const c_char,
+    headers_len: usize,
+) -> *mut c_void {
+    let Some(url) = cstr_to_rust(url) else {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "url is null or not valid UTF-8");
+        return std::ptr::null_mut();
+    };
+    let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
+
+    match RUNTIME.block_on(WsClient::connect(url, headers)) {
+        Ok(ws) => {
+            clear_last_error();
+            box_into_raw_new(ws) as *mut c_void
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_WS, e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_send_text(ws: *const c_void, text: *const c_char) -> bool {
+    let (ws, text) = match (ws.is_null(), cstr_to_rust(text)) {
+        (false, Some(text)) => (unsafe { &*(ws as *const WsClient) }, text),
+        _ => {
+            set_last_error(NGENRS_ERR_INVALID_ARG, "ws or text is null");
+            return false;
+        }
+    };
+    if collect_for_reconnect(ws, || WsMessage::Text(text.to_string())) {
+        clear_last_error();
+        return true;
+    }
+    ws_result(RUNTIME.block_on(ws.send_text(text)))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_send_binary(ws: *const c_void, data: *const u8, len: usize) -> bool {
+    let (ws, data) = match (ws.is_null(), cbytes_to_rust(data, len)) {
+        (false, Some(data)) => (unsafe { &*(ws as *const WsClient) }, data),
+        _ => {
+            set_last_error(NGENRS_ERR_INVALID_ARG, "ws or data is null");
+            return false;
+        }
+    };
+    if collect_for_reconnect(ws, || WsMessage::Binary(data.to_vec())) {
+        clear_last_error();
+        return true;
+    }
+    ws_result(RUNTIME.block_on(ws.send_binary(data)))
+}
+
+/// Enables or disables reconnecting after the connection drops without a closing
+/// handshake, which happens inside `ngenrs_ws_recv`. Up to `max_attempts` connections
+/// are tried, backing off from `base_delay_ms` up to `max_delay_ms`. Messages sent
+/// while disconnected are queued, up to `max_queued`, or dropped if it is negative.
+/// A null `on_reconnect` keeps the one set before, if any. Must be called before the handle is used from other threads.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_set_auto_reconnect(
+    ws: *mut c_void,
+    enabled: bool,
+    max_attempts: u32,
+    base_delay_ms: u64,
+    max_delay_ms: u64,
+    max_queued: i64,
+    on_reconnect: Option<NgenrsWsReconnectCallback>,
+    userdata: *mut c_void,
+) -> bool {
+    if ws.is_null() {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "ws is null");
+        return false;
+    }
+    let ws = unsafe { &mut *(ws as *mut WsClient) };
+    ws.set_auto_reconnect(enabled.then(|| WsReconnect {
+        retry: RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(base_delay_ms),
+            max_delay: Duration::from_millis(max_delay_ms),
+            ..RetryPolicy::default()
+        },
+        offline: usize::try_from(max_queued).map_or(WsOfflineSends::Drop, WsOfflineSends::Buffer),
+    }));
+    if let Some(on_reconnect) = on_reconnect {
+        // Raw pointers are not Send, so they cross into the hook as addresses
+        let (owner, userdata) = (ws as *const WsClient as usize, userdata as usize);
+        ws.set_on_reconnect(move || {
+            RECONNECT_OUTBOX.set(Some((owner, Vec::new())));
+            on_reconnect(userdata as *mut c_void);
+            RECONNECT_OUTBOX.take().map(|(_, messages)| messages).unwrap_or_default()
+        });
+    }
+    clear_last_error();
+    true
+}
+
+/// Sends a ping, e.g. periodically to keep an idle connection open. Server pings
+/// are answered automatically while `ngenrs_ws_recv` runs.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_ping(ws: *const c_void) -> bool {
+    if ws.is_null() {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "ws is null");
+        return false;
+    }
+    let ws = unsafe { &*(ws as *const WsClient) };
+    ws_result(RUNTIME.block_on(ws.ping(&[])))
+}
+
+/// Blocks until the next message arrives and passes it to `on_message`. Returns
+/// true if a message was delivered, including the server's close; false once the
+/// connection is closed or on error, which `ngenrs_last_error_code` tells apart.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_recv(ws: *const c_void, on_message: Option<NgenrsWsMessageCallback>, userdata: *mut c_void) -> bool {
+    let (false, Some(on_message)) = (ws.is_null(), on_message) else {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "ws or on_message is null");
+        return false;
+    };
+    let ws = unsafe { &*(ws as *const WsClient) };
+
+    let (kind, payload) = match RUNTIME.block_on(ws.recv()) {
+        Ok(Some(WsMessage::Text(text))) => (NGENRS_WS_TEXT, text.into_bytes()),
+        Ok(Some(WsMessage::Binary(data))) => (NGENRS_WS_BINARY, data),
+        Ok(Some(WsMessage::Close(frame))) => {
+            let payload = frame.map_or_else(Vec::new, |(code, reason)| {
+                let mut payload = code.to_be_bytes().to_vec();
+                payload.extend_from_slice(reason.as_bytes());
+                payload
+            });
+            (NGENRS_WS_CLOSE, payload)
+        }
+        Ok(None) => {
+            clear_last_error();
+            return false;
+        }
+        Err(e) => {
+            set_last_error(NGENRS_ERR_WS, e.to_string());
+            return false;
+        }
+    };
+    clear_last_error();
+    on_message(kind, payload.as_ptr(), payload.len(), userdata);
+    true
+}
+
+/// Performs the closing handshake; the handle must still be released afterwards.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_close(ws: *const c_void) -> bool {
+    if ws.is_null() {
+        set_last_error(NGENRS_ERR_INVALID_ARG, "ws is null");
+        return false;
+    }
+    let ws = unsafe { &*(ws as *const WsClient) };
+    ws_result(RUNTIME.block_on(ws.close()))
+}
+
+/// Drops the connection without a closing handshake if `ngenrs_ws_close` was not called.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_release(ws: *mut c_void) {
+    ngenrs_free_ptr(ws as *mut WsClient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::c::util::ngenrs_last_error_code;
+
+    extern "C" fn collect(kind: i32, data: *const u8, len: usize, userdata: *mut c_void) {
+        let received = unsafe { &mut *(userdata as *mut Vec<(i32, Vec<u8>)>) };
+        received.push((kind, unsafe { std::slice::from_raw_parts(data, len) }.to_vec()));
+    }
+
+    #[test]
+    fn messages_and_the_server_close_reach_the_callback() {
+        let listener = RUNTIME.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+        let url = CString::new(format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+        RUNTIME.spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            while let Some(Ok(message)) = socket.next().await {
+                match message {
+                    Message::Text(text) if text == "bye" => {
+                        let frame = CloseFrame { code: CloseCode::from(4000), reason: "done".into() };
+                        socket.close(Some(frame)).await.unwrap();
+                    }
+                    Message::Text(_) | Message::Binary(_) => socket.send(message).await.unwrap(),
+                    _ => {}
+                }
+            }
+        });
+
+        let ws = ngenrs_ws_connect(url.as_ptr(), std::ptr::null(), std::ptr::null(), 0);
+        assert!(!ws.is_null());
+        let mut received: Vec<(i32, Vec<u8>)> = Vec::new();
+        let userdata = &mut received as *mut Vec<(i32, Vec<u8>)> as *mut c_void;
+
+        let hello = CString::new("hello").unwrap();
+        assert!(ngenrs_ws_send_text(ws, hello.as_ptr()));
+        assert!(ngenrs_ws_recv(ws, Some(collect), userdata));
+        assert!(ngenrs_ws_send_binary(ws, [0u8, 1, 255].as_ptr(), 3));
+        assert!(ngenrs_ws_ping(ws));
+        assert!(ngenrs_ws_recv(ws, Some(collect), userdata));
+        let bye = CString::new("bye").unwrap();
+        assert!(ngenrs_ws_send_text(ws, bye.as_ptr()));
+        assert!(ngenrs_ws_recv(ws, Some(collect), userdata));
+
+        // Closed by the server: no more messages, and not an error
+        assert!(!ngenrs_ws_recv(ws, Some(collect), userdata));
+        assert_eq!(ngenrs_last_error_code(), crate::c::util::NGENRS_ERR_NONE);
+        assert!(ngenrs_ws_close(ws));
+        ngenrs_ws_release(ws);
+
+        let mut close = 4000u16.to_be_bytes().to_vec();
+        close.extend_from_slice(b"done");
+        assert_eq!(received, [
+            (NGENRS_WS_TEXT, b"hello".to_vec()),
+            (NGENRS_WS_BINARY, vec![0, 1, 255]),
+            (NGENRS_WS_CLOSE, close),
+        ]);
+    }
+
+    #[test]
+    fn null_arguments_are_rejected() {
+        assert!(ngenrs_ws_connect(std::ptr::null(), std::ptr::null(), std::ptr::null(), 0).is_null());
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_INVALID_ARG);
+        assert!(!ngenrs_ws_recv(std::ptr::null(), Some(collect), std::ptr::null_mut()));
+        assert_eq!(ngenrs_last_error_code(), NGENRS_ERR_INVALID_ARG);
+    }
+}
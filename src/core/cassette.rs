@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode, Url, Version};
+use serde_json::{json, Value};
+use crate::core::crypto::{base64_decode, base64_encode};
+use crate::core::net::{decode_text, HttpResponse, HttpTimings};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send requests normally and append each exchange to the cassette file.
+    Record,
+    /// Answer requests from the cassette file without touching the network.
+    Replay,
+    /// Send requests normally and leave the cassette alone.
+    Passthrough,
+}
+
+#[derive(Debug)]
+pub struct CassetteMiss {
+    pub method: Method,
+    pub url: Url,
+}
+
+impl std::fmt::Display for CassetteMiss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no recorded interaction for {} {}", self.method, self.url)
+    }
+}
+
+impl Error for CassetteMiss {}
+
+struct Interaction {
+    method: Method,
+    url: Url,
+    body: Vec<u8>,
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    response_body: Option<Vec<u8>>,
+    final_url: Url,
+    replayed: bool,
+}
+
+/// Recorded HTTP exchanges kept in a JSON file. Requests match a recording on
+/// method, URL and body; repeated requests replay matching recordings in order,
+/// then keep getting the last one.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Interaction {
+    fn to_json(&self) -> Value {
+        let headers = self.headers.iter()
+            .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+            .collect::<Vec<_>>();
+        json!({
+            "method": self.method.as_str(),
+            "url": self.url.as_str(),
+            "body": String::from_utf8_lossy(&base64_encode(&self.body)),
+            "status": self.status.as_u16(),
+            "version": format!("{:?}", self.version),
+            "headers": headers,
+            // The text is for reading the file; replay uses the exact bytes
+            "response_body": self.response_body.as_deref().map(|body| decode_text(&self.headers, body)),
+            "response_body_bytes": self.response_body.as_deref().map(|body| String::from_utf8_lossy(&base64_encode(body)).into_owned()),
+            "final_url": self.final_url.as_str(),
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self, Box<dyn Error>> {
+        let text = |field: &str| value[field].as_str()
+            .ok_or_else(|| format!("cassette entry has no {}", field));
+        let mut headers = HeaderMap::new();
+        for pair in value["headers"].as_array().into_iter().flatten() {
+            if let (Some(name), Some(value)) = (pair[0].as_str(), pair[1].as_str()) {
+                headers.append(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+            }
+        }
+        Ok(Self {
+            method: Method::from_bytes(text("method")?.as_bytes())?,
+            url: Url::parse(text("url")?)?,
+            body: base64_decode(text("body")?.as_bytes()),
+            status: StatusCode::from_u16(value["status"].as_u64().unwrap_or(0) as u16)?,
+            version: match value["version"].as_str() {
+                Some("HTTP/0.9") => Version::HTTP_09,
+                Some("HTTP/1.0") => Version::HTTP_10,
+                Some("HTTP/2.0") => Version::HTTP_2,
+                Some("HTTP/3.0") => Version::HTTP_3,
+                _ => Version::HTTP_11,
+            },
+            headers,
+            // Null for a response without a body, such as a 304
+            response_body: match value.get("response_body_bytes") {
+                Some(Value::Null) => None,
+                Some(_) => Some(base64_decode(text("response_body_bytes")?.as_bytes())),
+                None => return Err("cassette entry has no response_body_bytes".into()),
+            },
+            final_url: Url::parse(text("final_url")?)?,
+            replayed: false,
+        })
+    }
+
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse {
+            status: self.status,
+            version: self.version,
+            headers: self.headers.clone(),
+            body: self.response_body.as_deref().map(|body| decode_text(&self.headers, body)),
+            body_bytes: self.response_body.clone(),
+            final_url: self.final_url.clone(),
+            timings: HttpTimings::default(),
+        }
+    }
+}
+
+impl Cassette {
+    /// Loads `path` in Replay mode and starts a fresh recording in Record mode.
+    pub fn open(path: &Path, mode: CassetteMode) -> Result<Self, Box<dyn Error>> {
+        let interactions = match mode {
+            CassetteMode::Replay => {
+                let entries: Vec<Value> = serde_json::from_slice(&std::fs::read(path)?)?;
+                entries.iter().map(Interaction::from_json).collect::<Result<_, _>>()?
+            }
+            CassetteMode::Record | CassetteMode::Passthrough => Vec::new(),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode,
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub(crate) fn replay(&self, method: &Method, url: &Url, body: &[u8]) -> Result<HttpResponse, CassetteMiss> {
+        let mut interactions = self.interactions.lock().unwrap();
+        let matching = interactions.iter()
+            .enumerate()
+            .filter(|(_, i)| i.method == *method && i.url == *url && i.body == body)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let index = matching.iter()
+            .copied()
+            .find(|&index| !interactions[index].replayed)
+            .or(matching.last().copied())
+            .ok_or_else(|| CassetteMiss { method: method.clone(), url: url.clone() })?;
+        interactions[index].replayed = true;
+        Ok(interactions[index].to_response())
+    }
+
+    /// Appends one exchange and rewrites the cassette file.
+    pub(crate) fn record(
+        &self,
+        method: &Method,
+        url: &Url,
+        body: &[u8],
+        response: &HttpResponse,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(Interaction {
+            method: method.clone(),
+            url: url.clone(),
+            body: body.to_vec(),
+            status: response.status,
+            version: response.version,
+            headers: response.headers.clone(),
+            response_body: response.body_bytes.clone(),
+            final_url: response.final_url.clone(),
+            replayed: false,
+        });
+        let entries = interactions.iter().map(Interaction::to_json).collect::<Vec<_>>();
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&entries)?)?;
+        Ok(())
+    }
+}
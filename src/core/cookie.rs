@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use serde_json::{json, Value};
+
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    // Set without a Domain attribute, so only sent back to the exact host
+    host_only: bool,
+    path: String,
+    secure: bool,
+    // Seconds since the Unix epoch; `None` for session cookies
+    expires: Option<u64>,
+}
+
+/// Cookies an `HttpClient` received through `Set-Cookie`, sent back on later
+/// requests whose host, path and scheme match. Follows the RFC 6265 matching
+/// rules but has no public suffix list, so a `Domain=com` cookie is accepted.
+#[derive(Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// The directory of the request path, e.g. "/a/b" for "/a/b/c"
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => url.path()[..end].to_string(),
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path) && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+impl Cookie {
+    // `None` for a malformed header or a Domain the URL's host is not part of
+    fn parse(set_cookie: &str, url: &Url) -> Option<Self> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain_matches(&host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => {
+                    if let Ok(time) = httpdate::parse_http_date(value) {
+                        cookie.expires = Some(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires; zero or less expires the cookie right away
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(if max_age > 0 { now().saturating_add(max_age as u64) } else { 0 });
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    // Cookies with the same name, domain and path replace each other
+    fn same_slot(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let host_ok = if self.host_only { host == self.domain } else { domain_matches(&host, &self.domain) };
+        host_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "value": self.value,
+            "domain": self.domain,
+            "host_only": self.host_only,
+            "path": self.path,
+            "secure": self.secure,
+            "expires": self.expires,
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self, Box<dyn Error>> {
+        let text = |field: &str| value[field].as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("cookie entry has no {}", field));
+        Ok(Self {
+            name: text("name")?,
+            value: text("value")?,
+            domain: text("domain")?,
+            host_only: value["host_only"].as_bool().unwrap_or(true),
+            path: text("path")?,
+            secure: value["secure"].as_bool().unwrap_or(false),
+            expires: value["expires"].as_u64(),
+        })
+    }
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the cookie in a `Set-Cookie` value received from `url`, replacing one
+    /// with the same name, domain and path. Malformed values are ignored.
+    pub fn store(&self, set_cookie: &str, url: &Url) {
+        let Some(cookie) = Cookie::parse(set_cookie, url) else {
+            return;
+        };
+        let now = now();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| !c.is_expired(now) && !c.same_slot(&cookie));
+        if !cookie.is_expired(now) {
+            cookies.push(cookie);
+        }
+    }
+
+    /// The `Cookie` header value for a request to `url`, longest paths first.
+    pub fn header_for(&self, url: &Url) -> Option<String> {
+        let now = now();
+        let cookies = self.cookies.lock().unwrap();
+        let mut matching = cookies.iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(url))
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        let pairs = matching.iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>();
+        Some(pairs.join("; "))
+    }
+
+    pub fn clear(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+
+    /// Writes the unexpired cookies to `path` as JSON. Session cookies are kept
+    /// too, so a restarted process can resume the session.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let now = now();
+        let entries = self.cookies.lock().unwrap().iter()
+            .filter(|cookie| !cookie.is_expired(now))
+            .map(Cookie::to_json)
+            .collect::<Vec<_>>();
+        std::fs::write(path, serde_json::to_vec_pretty(&entries)?)?;
+        Ok(())
+    }
+
+    /// Replaces the jar's cookies with those saved at `path` by `save`.
+    pub fn load(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let entries: Vec<Value> = serde_json::from_slice(&std::fs::read(path)?)?;
+        let now = now();
+        let cookies = entries.iter()
+            .map(Cookie::from_json)
+            .filter(|cookie| !cookie.as_ref().is_ok_and(|cookie| cookie.is_expired(now)))
+            .collect::<Result<Vec<_>, _>>()?;
+        *self.cookies.lock().unwrap() = cookies;
+        Ok(())
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        for value in cookie_headers {
+            if let Ok(value) = value.to_str() {
+                self.store(value, url);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.header_for(url).and_then(|header| HeaderValue::from_str(&header).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+
+    #[test]
+    fn saved_cookies_come_back_after_a_reload() {
+        let url = Url::parse("https://api.example.com/account/login").unwrap();
+        let jar = CookieJar::new();
+        jar.store("session=old; Path=/", &url);
+        jar.store("session=new; Path=/", &url);
+        jar.store("prefs=dark; Domain=example.com; Path=/account; Secure", &url);
+        jar.store("gone=1; Max-Age=0", &url);
+
+        let path = TempPath::new("cookies.json");
+        jar.save(&path).unwrap();
+        let reloaded = CookieJar::new();
+        reloaded.load(&path).unwrap();
+
+        let account = Url::parse("https://www.example.com/account/settings").unwrap();
+        assert_eq!(reloaded.header_for(&account).as_deref(), Some("prefs=dark"));
+        let home = Url::parse("https://api.example.com/account").unwrap();
+        assert_eq!(reloaded.header_for(&home).as_deref(), Some("prefs=dark; session=new"));
+        let insecure = Url::parse("http://api.example.com/").unwrap();
+        assert_eq!(reloaded.header_for(&insecure).as_deref(), Some("session=new"));
+    }
+}
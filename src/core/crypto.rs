@@ -13,7 +13,7 @@ use rsa::{
     Pkcs1v15Encrypt, 
     Oaep
 };
-use rsa::rand_core::OsRng;
+use rsa::rand_core::{OsRng, RngCore};
 use md5::{Md5, Digest};
 use sha1::Sha1;
 use sha2::Sha256;
@@ -125,6 +125,13 @@ pub fn rsa_dec(input: Vec<u8>, private_key: Vec<u8>, padding: i32) -> Vec<u8> {
     }
 }
 
+/// `len` bytes from the operating system's CSPRNG.
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
 pub fn hash<D: Digest>(data: &[u8]) -> Vec<u8> {
     let mut hasher = D::new();
     hasher.update(data);
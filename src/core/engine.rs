@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::runtime::{Handle, Runtime};
+use crate::core::kv::KV;
+use crate::core::lua::LuaBridge;
+use crate::core::net::{HttpClient, HttpClientConfig};
+
+const DEFAULT_KV_PATH: &str = "dynrs.kv";
+
+/// Owns one of each subsystem, with the `http` and `kv` Lua bindings already installed.
+pub struct Engine {
+    lua: LuaBridge,
+    http: Arc<HttpClient>,
+    kv: Arc<KV>,
+    runtime: Handle,
+    // The runtime started for this engine when the builder was given none. Declared
+    // last so it outlives everything that may hold runtime resources
+    _owned_runtime: Option<Runtime>,
+}
+
+pub struct EngineBuilder {
+    kv_path: PathBuf,
+    http_config: HttpClientConfig,
+    runtime: Option<Handle>,
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder {
+            kv_path: PathBuf::from(DEFAULT_KV_PATH),
+            http_config: HttpClientConfig::default(),
+            runtime: None,
+        }
+    }
+
+    pub fn lua(&self) -> &LuaBridge {
+        &self.lua
+    }
+
+    pub fn http(&self) -> &Arc<HttpClient> {
+        &self.http
+    }
+
+    pub fn kv(&self) -> &Arc<KV> {
+        &self.kv
+    }
+
+    pub fn runtime(&self) -> &Handle {
+        &self.runtime
+    }
+}
+
+impl EngineBuilder {
+    /// Database file for the KV store; defaults to `dynrs.kv` in the working directory.
+    pub fn kv_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.kv_path = path.into();
+        self
+    }
+
+    pub fn http_config(mut self, config: HttpClientConfig) -> Self {
+        self.http_config = config;
+        self
+    }
+
+    /// Runs blocking calls from scripts on `runtime` instead of a runtime of the engine's own.
+    pub fn runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    pub fn build(self) -> Result<Engine, Box<dyn Error>> {
+        let (runtime, owned_runtime) = match self.runtime {
+            Some(runtime) => (runtime, None),
+            None => {
+                let owned = Runtime::new()?;
+                (owned.handle().clone(), Some(owned))
+            }
+        };
+        let http = Arc::new(HttpClient::with_config(self.http_config)?);
+        let kv = Arc::new(KV::open(&self.kv_path)?);
+
+        let lua = LuaBridge::new()?;
+        lua.bind_http(http.clone(), runtime.clone())?;
+        lua.bind_kv(kv.clone())?;
+
+        Ok(Engine { lua, http, kv, runtime, _owned_runtime: owned_runtime })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response, serve, TempPath};
+
+    #[test]
+    fn scripts_reach_http_and_kv() {
+        let url = serve(|_| response(200, &[], b"fetched"));
+        let path = TempPath::new("engine.redb");
+        let engine = Engine::builder().kv_path(&*path).build().unwrap();
+
+        engine.lua().load_string(&format!(r#"
+            local response = http.get("{}")
+            kv.setString("page", response:status() .. " " .. response:body())
+        "#, url)).unwrap();
+        assert_eq!(engine.kv().read_string("page").unwrap().as_deref(), Some("200 fetched"));
+    }
+
+    #[test]
+    fn a_given_runtime_is_used_instead_of_a_new_one() {
+        let url = serve(|_| response(200, &[], b"fetched"));
+        let path = TempPath::new("engine.redb");
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("given-runtime")
+            .enable_all()
+            .build()
+            .unwrap();
+        let engine = Engine::builder().kv_path(&*path).runtime(runtime.handle().clone()).build().unwrap();
+
+        let worker = engine.runtime().block_on(async {
+            tokio::spawn(async { std::thread::current().name().map(str::to_string) }).await.unwrap()
+        });
+        assert_eq!(worker.as_deref(), Some("given-runtime"));
+        engine.lua().load_string(&format!(r#"kv.setString("page", http.get("{}"):body())"#, url)).unwrap();
+        assert_eq!(engine.kv().read_string("page").unwrap().as_deref(), Some("fetched"));
+    }
+}
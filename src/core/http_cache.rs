@@ -0,0 +1,285 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{StatusCode, Url};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use crate::core::crypto::{base64_decode, base64_encode, bytes2hex};
+use crate::core::kv::KV;
+use crate::core::net::{decode_text, HttpClient, HttpResponse, HttpTimings};
+
+// Entries live in the string table under "__http_cache_layer:<sha256 of URL and headers>"
+pub(crate) const KEY_PREFIX: &str = "__http_cache_layer:";
+
+struct Entry {
+    headers: HeaderMap,
+    body: Vec<u8>,
+    final_url: Url,
+    // Seconds since the Unix epoch
+    stored_at: u64,
+    // From `Cache-Control: max-age`; `None` means every use is revalidated
+    max_age: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// The directives of interest in `Cache-Control`: no-store, and max-age (0 for no-cache)
+fn cache_control(headers: &HeaderMap) -> (bool, Option<u64>) {
+    let mut no_store = false;
+    let mut max_age = None;
+    for directive in headers.get_all(header::CACHE_CONTROL).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+        match name.trim().to_ascii_lowercase().as_str() {
+            "no-store" => no_store = true,
+            "no-cache" => max_age = Some(0),
+            "max-age" => max_age = max_age.or(value.trim().trim_matches('"').parse().ok()),
+            _ => {}
+        }
+    }
+    (no_store, max_age)
+}
+
+impl Entry {
+    fn is_fresh(&self, now: u64) -> bool {
+        self.max_age.is_some_and(|max_age| now < self.stored_at.saturating_add(max_age))
+    }
+
+    fn to_json(&self) -> Value {
+        let headers = self.headers.iter()
+            .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+            .collect::<Vec<_>>();
+        json!({
+            "headers": headers,
+            "body": String::from_utf8_lossy(&base64_encode(&self.body)),
+            "final_url": self.final_url.as_str(),
+            "stored_at": self.stored_at,
+            "max_age": self.max_age,
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self, Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
+        for pair in value["headers"].as_array().into_iter().flatten() {
+            if let (Some(name), Some(value)) = (pair[0].as_str(), pair[1].as_str()) {
+                headers.append(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+            }
+        }
+        Ok(Self {
+            headers,
+            body: base64_decode(value["body"].as_str().unwrap_or_default().as_bytes()),
+            final_url: Url::parse(value["final_url"].as_str().ok_or("cache entry has no final_url")?)?,
+            stored_at: value["stored_at"].as_u64().unwrap_or(0),
+            max_age: value["max_age"].as_u64(),
+        })
+    }
+
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse {
+            status: StatusCode::OK,
+            version: reqwest::Version::HTTP_11,
+            headers: self.headers.clone(),
+            body: Some(decode_text(&self.headers, &self.body)),
+            body_bytes: Some(self.body.clone()),
+            final_url: self.final_url.clone(),
+            timings: HttpTimings::default(),
+        }
+    }
+}
+
+/// Caches `200` GET responses of an `HttpClient` in a KV store, keyed on the URL
+/// and request headers. A response is answered from the cache while within its
+/// `Cache-Control: max-age`, and afterwards revalidated with `If-None-Match` and
+/// `If-Modified-Since`; a `304 Not Modified` then returns the cached body with
+/// status 200. Responses marked `no-store` are never stored and evict the entry.
+pub struct CacheLayer<'a> {
+    client: &'a HttpClient,
+    kv: &'a KV,
+}
+
+impl<'a> CacheLayer<'a> {
+    pub fn new(client: &'a HttpClient, kv: &'a KV) -> Self {
+        Self { client, kv }
+    }
+
+    // Header names are case-insensitive, so they are lowercased and sorted first
+    fn key(url: &str, headers: &HashMap<String, String>) -> String {
+        let mut lines = headers.iter()
+            .map(|(name, value)| format!("{}:{}", name.to_ascii_lowercase(), value))
+            .collect::<Vec<_>>();
+        lines.sort();
+        let digest = Sha256::digest(format!("{}\n{}", url, lines.join("\n")).as_bytes());
+        format!("{}{}", KEY_PREFIX, bytes2hex(&digest))
+    }
+
+    fn load(&self, key: &str) -> Result<Option<Entry>, Box<dyn Error>> {
+        match self.kv.read_string(key) {
+            // An entry that no longer parses is treated as missing and overwritten
+            Ok(Some(data)) => Ok(serde_json::from_str::<Value>(&data).ok()
+                .and_then(|value| Entry::from_json(&value).ok())),
+            Ok(None) | Err(redb::Error::TableDoesNotExist(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn get<K, V>(&self, url: &str, headers: Option<HashMap<K, V>>) -> Result<HttpResponse, Box<dyn Error>>
+    where
+        K: Borrow<str>,
+        V: Borrow<str>,
+    {
+        let headers: HashMap<String, String> = headers.into_iter()
+            .flatten()
+            .map(|(k, v)| (k.borrow().to_string(), v.borrow().to_string()))
+            .collect();
+        let key = Self::key(url, &headers);
+        let cached = self.load(&key)?;
+        if let Some(entry) = cached.as_ref().filter(|entry| entry.is_fresh(now())) {
+            return Ok(entry.to_response());
+        }
+
+        let mut request_headers = headers;
+        if let Some(entry) = &cached {
+            for (validator, condition) in [(header::ETAG, header::IF_NONE_MATCH), (header::LAST_MODIFIED, header::IF_MODIFIED_SINCE)] {
+                if let Some(value) = entry.headers.get(validator).and_then(|v| v.to_str().ok()) {
+                    request_headers.insert(condition.to_string(), value.to_string());
+                }
+            }
+        }
+
+        let response = self.client.get(url, Some(request_headers), None, None, None).await?;
+        let (no_store, max_age) = cache_control(&response.headers);
+        let (entry, response) = match cached {
+            Some(mut entry) if response.status == StatusCode::NOT_MODIFIED => {
+                // A 304 carries the current validators and freshness; they replace the stored ones
+                for (name, value) in &response.headers {
+                    if [header::ETAG, header::LAST_MODIFIED, header::CACHE_CONTROL, header::EXPIRES, header::DATE].contains(name) {
+                        entry.headers.insert(name, value.clone());
+                    }
+                }
+                entry.stored_at = now();
+                entry.max_age = cache_control(&entry.headers).1;
+                let response = entry.to_response();
+                (entry, response)
+            }
+            _ if response.status == StatusCode::OK => {
+                let entry = Entry {
+                    headers: response.headers.clone(),
+                    body: response.body_bytes.clone().unwrap_or_default(),
+                    final_url: response.final_url.clone(),
+                    stored_at: now(),
+                    max_age,
+                };
+                (entry, response)
+            }
+            _ => return Ok(response),
+        };
+
+        let revalidatable = entry.headers.contains_key(header::ETAG) || entry.headers.contains_key(header::LAST_MODIFIED);
+        if no_store {
+            self.kv.delete_string(&key)?;
+        } else if revalidatable || entry.max_age.is_some_and(|max_age| max_age > 0) {
+            self.kv.write_string(&key, &entry.to_json().to_string())?;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::test_util::{response, serve, temp_kv};
+
+    // Each request's target and `If-None-Match`
+    type Seen = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    // Answers with `respond(target, If-None-Match)`, recording every request
+    fn counting_server(respond: impl Fn(&str, Option<&str>) -> Vec<u8> + Send + Sync + 'static) -> (String, Seen) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = serve(move |request| {
+            let condition = request.header("if-none-match");
+            seen.lock().unwrap().push((request.target.clone(), condition.map(str::to_string)));
+            respond(&request.target, condition)
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn a_304_serves_the_cached_body() {
+        let (url, requests) = counting_server(|_, condition| match condition {
+            Some("\"v1\"") => response(304, &[("etag", "\"v1\"")], b""),
+            _ => response(200, &[("etag", "\"v1\""), ("content-type", "text/plain")], b"cached body"),
+        });
+        let client = HttpClient::new(None).unwrap();
+        let (kv, _path) = temp_kv();
+        let cache = CacheLayer::new(&client, &kv);
+
+        let first = cache.get::<&str, &str>(&url, None).await.unwrap();
+        assert_eq!(first.body.as_deref(), Some("cached body"));
+        let second = cache.get::<&str, &str>(&url, None).await.unwrap();
+        assert_eq!(second.status, StatusCode::OK);
+        assert_eq!(second.body.as_deref(), Some("cached body"));
+        assert_eq!(second.headers.get(header::CONTENT_TYPE).unwrap(), "text/plain");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.iter().map(|(_, condition)| condition.as_deref()).collect::<Vec<_>>(), [None, Some("\"v1\"")]);
+    }
+
+    #[tokio::test]
+    async fn fresh_entries_skip_the_network_until_max_age_passes() {
+        let (url, requests) = counting_server(|target, _| match target {
+            "/stale" => response(200, &[("cache-control", "max-age=0"), ("etag", "\"s\"")], b"stale"),
+            _ => response(200, &[("cache-control", "public, max-age=3600")], b"fresh"),
+        });
+        let client = HttpClient::new(None).unwrap();
+        let (kv, _path) = temp_kv();
+        let cache = CacheLayer::new(&client, &kv);
+
+        for _ in 0..3 {
+            let response = cache.get::<&str, &str>(&format!("{}/fresh", url), None).await.unwrap();
+            assert_eq!(response.body.as_deref(), Some("fresh"));
+        }
+        // Different request headers make a separate entry
+        let json = HashMap::from([("Accept", "application/json")]);
+        cache.get(&format!("{}/fresh", url), Some(json)).await.unwrap();
+        for _ in 0..2 {
+            cache.get::<&str, &str>(&format!("{}/stale", url), None).await.unwrap();
+        }
+
+        let requests = requests.lock().unwrap();
+        let paths = requests.iter().map(|(path, _)| path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["/fresh", "/fresh", "/stale", "/stale"]);
+        assert_eq!(requests[3].1.as_deref(), Some("\"s\""));
+    }
+
+    #[tokio::test]
+    async fn no_store_responses_are_never_cached_and_evict_the_entry() {
+        let private = Arc::new(Mutex::new(false));
+        let switch = private.clone();
+        let url = serve(move |_| {
+            let cache_control = if *switch.lock().unwrap() { "no-store" } else { "max-age=0" };
+            response(200, &[("cache-control", cache_control), ("etag", "\"e\"")], b"body")
+        });
+        let client = HttpClient::new(None).unwrap();
+        let (kv, _path) = temp_kv();
+        let cache = CacheLayer::new(&client, &kv);
+        let key = CacheLayer::key(&url, &HashMap::new());
+
+        cache.get::<&str, &str>(&url, None).await.unwrap();
+        assert!(kv.read_string(&key).unwrap().is_some());
+
+        *private.lock().unwrap() = true;
+        let response = cache.get::<&str, &str>(&url, None).await.unwrap();
+        assert_eq!(response.body.as_deref(), Some("body"));
+        assert!(kv.read_string(&key).unwrap().is_none());
+        cache.get::<&str, &str>(&url, None).await.unwrap();
+        assert!(kv.read_string(&key).unwrap().is_none());
+    }
+}
@@ -1,66 +1,1315 @@
-use redb::{Database, Error, TableDefinition};
-use std::path::Path;
+use redb::{Database, Error, ReadOnlyTable, ReadTransaction, ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle, WriteTransaction};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
 // Define table names for different value types
 const INT_TABLE: TableDefinition<&str, i64> = TableDefinition::new("integers");
 const FLOAT_TABLE: TableDefinition<&str, f64> = TableDefinition::new("floats");
 const STRING_TABLE: TableDefinition<&str, &str> = TableDefinition::new("strings");
 
+// redb keeps each value contiguous and caps it below 4 GiB, so blobs are split
+// into fixed-size chunks stored under the composite key (key, chunk index)
+const BLOB_TABLE: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("blobs");
+const BLOB_CHUNK_SIZE: usize = 1024 * 1024;
+
+// Expiry time of keys given a TTL, in milliseconds since the Unix epoch
+const EXPIRY_TABLE: TableDefinition<&str, u64> = TableDefinition::new("expiry");
+// Keys deleted per write transaction when purging, so writers are not held up long
+const PURGE_BATCH_SIZE: usize = 256;
+
+/// Version of the table layout above; bump it whenever a table is added or changes type.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KvValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+/// Selects one of the typed value tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Int,
+    Float,
+    String,
+}
+
+#[derive(Clone, Debug)]
+pub struct KvEvent {
+    pub key: String,
+    /// The value written, or `None` when the key was deleted.
+    pub value: Option<KvValue>,
+}
+
+type KeyWatcher = Arc<dyn Fn(&KvEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct Watchers {
+    next_id: u64,
+    by_key: HashMap<String, Vec<(u64, KeyWatcher)>>,
+}
+
+/// Unregisters its key watcher when dropped.
+pub struct KeyWatchGuard {
+    watchers: Arc<Mutex<Watchers>>,
+    key: String,
+    id: u64,
+}
+
+impl Drop for KeyWatchGuard {
+    fn drop(&mut self) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(list) = watchers.by_key.get_mut(&self.key) {
+            list.retain(|(id, _)| *id != self.id);
+            if list.is_empty() {
+                watchers.by_key.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Probabilistic set of every scalar key ever written. A negative answer is
+/// definite; a positive one is wrong with roughly the configured false-positive
+/// rate, which also grows as keys are deleted since bits are never cleared.
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(expected_keys: usize, false_positive_rate: f64) -> Self {
+        let n = expected_keys.max(1) as f64;
+        let p = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = ((-n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    // Double hashing: bit i is h1 + i * h2
+    fn positions(&self, key: &str) -> impl Iterator<Item = u64> + '_ {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        0xB10Fu16.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    fn insert(&mut self, key: &str) {
+        let positions: Vec<u64> = self.positions(key).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    fn might_contain(&self, key: &str) -> bool {
+        self.positions(key).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+type SharedBloomFilter = Arc<RwLock<BloomFilter>>;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KvStats {
+    pub read_transactions: u64,
+    pub bloom_filter_skips: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TableInfo {
+    pub name: String,
+    pub entries: u64,
+    /// Bytes held by keys and values, excluding b-tree overhead and fragmentation.
+    pub stored_bytes: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DatabaseInfo {
+    pub schema_version: u32,
+    pub file_size: u64,
+    pub tables: Vec<TableInfo>,
+}
+
+#[derive(Default)]
+struct KvCounters {
+    read_transactions: AtomicU64,
+    bloom_filter_skips: AtomicU64,
+}
+
+fn notify_watchers(watchers: &Mutex<Watchers>, key: &str, value: Option<KvValue>) {
+    // Snapshot the callbacks and call them unlocked, so they may write the store or
+    // (un)register watchers themselves
+    let callbacks: Vec<KeyWatcher> = match watchers.lock().unwrap().by_key.get(key) {
+        Some(list) => list.iter().map(|(_, cb)| cb.clone()).collect(),
+        None => return,
+    };
+    let event = KvEvent { key: key.to_string(), value };
+    for cb in callbacks {
+        cb(&event);
+    }
+}
+
 pub struct KV {
     db: Database,
+    path: PathBuf,
+    watchers: Arc<Mutex<Watchers>>,
+    bloom: Option<SharedBloomFilter>,
+    counters: KvCounters,
+    compute_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
 }
 
 impl KV {
     pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let db = Database::create(path)?;
-        Ok(Self { db })
+        let db = Database::create(path.as_ref())?;
+        Ok(Self::with_database(db, path.as_ref()))
+    }
+
+    /// Opens the store with a page cache of at most `cache_bytes` rather than the
+    /// default of 1 GiB, which otherwise fills up with what large blobs pass through.
+    pub fn open_with_cache_size(path: impl AsRef<Path>, cache_bytes: usize) -> Result<Self, Error> {
+        let db = Database::builder().set_cache_size(cache_bytes).create(path.as_ref())?;
+        Ok(Self::with_database(db, path.as_ref()))
+    }
+
+    fn with_database(db: Database, path: &Path) -> Self {
+        Self {
+            db,
+            path: path.to_path_buf(),
+            watchers: Arc::new(Mutex::new(Watchers::default())),
+            bloom: None,
+            counters: KvCounters::default(),
+            compute_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Opens the store with an in-memory bloom filter so reads of keys that were
+    /// never written return without opening a transaction. The filter is built by
+    /// scanning every key, so opening cost grows with the database; size it with
+    /// the expected key count, as memory is about 10 bits per key at a 1% rate.
+    pub fn open_with_bloom_filter(
+        path: impl AsRef<Path>,
+        expected_keys: usize,
+        false_positive_rate: f64,
+    ) -> Result<Self, Error> {
+        let mut kv = Self::open(path)?;
+        let mut filter = BloomFilter::new(expected_keys, false_positive_rate);
+        let read_txn = kv.db.begin_read()?;
+        add_table_keys(&read_txn, INT_TABLE, &mut filter)?;
+        add_table_keys(&read_txn, FLOAT_TABLE, &mut filter)?;
+        add_table_keys(&read_txn, STRING_TABLE, &mut filter)?;
+        drop(read_txn);
+        kv.bloom = Some(Arc::new(RwLock::new(filter)));
+        Ok(kv)
+    }
+
+    pub fn stats(&self) -> KvStats {
+        KvStats {
+            read_transactions: self.counters.read_transactions.load(Ordering::Relaxed),
+            bloom_filter_skips: self.counters.bloom_filter_skips.load(Ordering::Relaxed),
+        }
+    }
+
+    /// All keys in the `table` table, in lexicographic order.
+    pub fn list_keys(&self, table: ValueType) -> Result<Vec<String>, Error> {
+        Ok(self.list_keys_paginated(table, None, usize::MAX)?.0)
+    }
+
+    /// Returns up to `limit` keys that sort after the cursor `after`, and the cursor
+    /// for the next page, which is `None` once the table is exhausted.
+    pub fn list_keys_paginated(
+        &self,
+        table: ValueType,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<String>), Error> {
+        let read_txn = self.begin_read()?;
+        let (keys, more) = match table {
+            ValueType::Int => table_keys(&read_txn, INT_TABLE, after, limit)?,
+            ValueType::Float => table_keys(&read_txn, FLOAT_TABLE, after, limit)?,
+            ValueType::String => table_keys(&read_txn, STRING_TABLE, after, limit)?,
+        };
+        let next = if more { keys.last().cloned() } else { None };
+        Ok((keys, next))
+    }
+
+    /// Lists every table with its size, for diagnostics. Unlike `stats` this reads
+    /// the database itself, opening one read transaction.
+    pub fn describe(&self) -> Result<DatabaseInfo, Error> {
+        let read_txn = self.begin_read()?;
+        let mut tables = Vec::new();
+        for handle in read_txn.list_tables()? {
+            let name = handle.name().to_string();
+            let table = read_txn.open_untyped_table(handle)?;
+            tables.push(TableInfo {
+                name,
+                entries: table.len()?,
+                stored_bytes: table.stats()?.stored_bytes(),
+            });
+        }
+        Ok(DatabaseInfo {
+            schema_version: SCHEMA_VERSION,
+            file_size: std::fs::metadata(&self.path).map_err(Error::Io)?.len(),
+            tables,
+        })
+    }
+
+    // False only when the key has definitely never been written
+    fn might_contain(&self, key: &str) -> bool {
+        let found = match &self.bloom {
+            Some(bloom) => bloom.read().unwrap().might_contain(key),
+            None => true,
+        };
+        if !found {
+            self.counters.bloom_filter_skips.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    fn remember_key(&self, key: &str) {
+        if let Some(bloom) = &self.bloom {
+            bloom.write().unwrap().insert(key);
+        }
+    }
+
+    fn begin_read(&self) -> Result<ReadTransaction, Error> {
+        self.counters.read_transactions.fetch_add(1, Ordering::Relaxed);
+        Ok(self.db.begin_read()?)
+    }
+
+    /// Whether an int, float or string value is stored under `key`.
+    pub fn exists(&self, key: &str) -> Result<bool, Error> {
+        if !self.might_contain(key) {
+            return Ok(false);
+        }
+        let read_txn = self.begin_read()?;
+        let stored = table_contains(&read_txn, INT_TABLE, key)?
+            || table_contains(&read_txn, FLOAT_TABLE, key)?
+            || table_contains(&read_txn, STRING_TABLE, key)?;
+        Ok(stored && !is_expired(&read_txn, key)?)
+    }
+
+    /// Registers `cb` to be called after every committed write or delete of `key`.
+    /// A key may have several watchers; each lives until its guard is dropped.
+    /// Callbacks run on the writing thread and may themselves write the store.
+    pub fn watch_key(&self, key: &str, cb: impl Fn(&KvEvent) + Send + Sync + 'static) -> KeyWatchGuard {
+        let mut watchers = self.watchers.lock().unwrap();
+        let id = watchers.next_id;
+        watchers.next_id += 1;
+        let watcher: KeyWatcher = Arc::new(cb);
+        watchers.by_key.entry(key.to_string()).or_default().push((id, watcher));
+        KeyWatchGuard {
+            watchers: self.watchers.clone(),
+            key: key.to_string(),
+            id,
+        }
+    }
+
+    fn notify(&self, key: &str, value: Option<KvValue>) {
+        notify_watchers(&self.watchers, key, value);
+    }
+
+    /// Starts a write transaction; nothing is visible to readers until `commit`,
+    /// and dropping it uncommitted rolls every change back.
+    pub fn begin_transaction(&self) -> Result<KvTransaction, Error> {
+        Ok(KvTransaction {
+            txn: self.db.begin_write()?,
+            watchers: self.watchers.clone(),
+            bloom: self.bloom.clone(),
+            changes: Vec::new(),
+        })
+    }
+
+    /// Opens a read-only view pinned to the current state of the database; later
+    /// writes are invisible to it. Holding it keeps old pages from being reused.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        Ok(Snapshot { txn: self.begin_read()? })
+    }
+
+    /// Runs `f` inside one write transaction, committing if it returns `Ok`.
+    pub fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        E: From<Error>,
+        F: FnOnce(&mut KvTransaction) -> Result<T, E>,
+    {
+        let mut tx = self.begin_transaction()?;
+        let value = f(&mut tx)?;
+        tx.commit()?;
+        Ok(value)
     }
 
     pub fn write_int(&self, key: &str, value: i64) -> Result<(), Error> {
+        self.remember_key(key);
         let write_txn = self.db.begin_write()?;
         {
             let mut table = write_txn.open_table(INT_TABLE)?;
             table.insert(key, value)?;
         }
+        take_expiry(&write_txn, key)?;
         write_txn.commit()?;
+        self.notify(key, Some(KvValue::Int(value)));
         Ok(())
     }
 
     pub fn read_int(&self, key: &str) -> Result<Option<i64>, Error> {
-        let read_txn = self.db.begin_read()?;
+        if !self.might_contain(key) {
+            return Ok(None);
+        }
+        let read_txn = self.begin_read()?;
         let table = read_txn.open_table(INT_TABLE)?;
-        Ok(table.get(key)?.map(|x| x.value()))
+        let value = table.get(key)?.map(|x| x.value());
+        if value.is_some() && is_expired(&read_txn, key)? {
+            return Ok(None);
+        }
+        Ok(value)
     }
 
     pub fn write_float(&self, key: &str, value: f64) -> Result<(), Error> {
+        self.remember_key(key);
         let write_txn = self.db.begin_write()?;
         {
             let mut table = write_txn.open_table(FLOAT_TABLE)?;
             table.insert(key, value)?;
         }
+        take_expiry(&write_txn, key)?;
         write_txn.commit()?;
+        self.notify(key, Some(KvValue::Float(value)));
         Ok(())
     }
 
     pub fn read_float(&self, key: &str) -> Result<Option<f64>, Error> {
-        let read_txn = self.db.begin_read()?;
+        if !self.might_contain(key) {
+            return Ok(None);
+        }
+        let read_txn = self.begin_read()?;
         let table = read_txn.open_table(FLOAT_TABLE)?;
-        Ok(table.get(key)?.map(|x| x.value()))
+        let value = table.get(key)?.map(|x| x.value());
+        if value.is_some() && is_expired(&read_txn, key)? {
+            return Ok(None);
+        }
+        Ok(value)
     }
 
     pub fn write_string(&self, key: &str, value: &str) -> Result<(), Error> {
+        self.remember_key(key);
         let write_txn = self.db.begin_write()?;
         {
             let mut table = write_txn.open_table(STRING_TABLE)?;
             table.insert(key, value)?;
         }
+        take_expiry(&write_txn, key)?;
         write_txn.commit()?;
+        self.notify(key, Some(KvValue::String(value.to_string())));
         Ok(())
     }
 
     pub fn read_string(&self, key: &str) -> Result<Option<String>, Error> {
-        let read_txn = self.db.begin_read()?;
+        if !self.might_contain(key) {
+            return Ok(None);
+        }
+        let read_txn = self.begin_read()?;
         let table = read_txn.open_table(STRING_TABLE)?;
-        Ok(table.get(key)?.map(|x| x.value().to_string()))
+        let value = table.get(key)?.map(|x| x.value().to_string());
+        if value.is_some() && is_expired(&read_txn, key)? {
+            return Ok(None);
+        }
+        Ok(value)
+    }
+
+    /// Returns every string entry whose key starts with `prefix`, in key order.
+    /// An empty prefix matches all keys.
+    pub fn scan_prefix_string(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let read_txn = self.begin_read()?;
+        let table = match read_txn.open_table(STRING_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let expiry = open_expiry(&read_txn)?;
+        let mut entries = Vec::new();
+        for entry in table.range(prefix..)? {
+            let (key, value) = entry?;
+            if !key.value().starts_with(prefix) {
+                break;
+            }
+            if expiry.as_ref().map_or(Ok(false), |expiry| is_expired_in(expiry, key.value()))? {
+                continue;
+            }
+            entries.push((key.value().to_string(), value.value().to_string()));
+        }
+        Ok(entries)
+    }
+
+    /// Expires the int, float or string value under `key` once `ttl` has elapsed,
+    /// replacing any earlier TTL. Expired values read as missing right away, to every
+    /// reader including snapshots and transactions, but stay on disk until
+    /// `purge_expired` runs. Any later write or delete of `key` clears its TTL.
+    pub fn expire(&self, key: &str, ttl: Duration) -> Result<(), Error> {
+        let expires_at = unix_millis(SystemTime::now() + ttl);
+        let write_txn = self.db.begin_write()?;
+        write_txn.open_table(EXPIRY_TABLE)?.insert(key, expires_at)?;
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Deletes every expired value and returns how many keys were removed. Expired
+    /// keys are found in a read transaction and deleted in small write batches.
+    pub fn purge_expired(&self) -> Result<usize, Error> {
+        let now = unix_millis(SystemTime::now());
+        let expired = {
+            let read_txn = self.begin_read()?;
+            let table = match read_txn.open_table(EXPIRY_TABLE) {
+                Ok(table) => table,
+                Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+                Err(e) => return Err(e.into()),
+            };
+            let mut expired = Vec::new();
+            for entry in table.iter()? {
+                let (key, expires_at) = entry?;
+                if expires_at.value() <= now {
+                    expired.push(key.value().to_string());
+                }
+            }
+            expired
+        };
+
+        let mut removed = 0;
+        for batch in expired.chunks(PURGE_BATCH_SIZE) {
+            let write_txn = self.db.begin_write()?;
+            {
+                let mut expiry = write_txn.open_table(EXPIRY_TABLE)?;
+                let mut ints = write_txn.open_table(INT_TABLE)?;
+                let mut floats = write_txn.open_table(FLOAT_TABLE)?;
+                let mut strings = write_txn.open_table(STRING_TABLE)?;
+                for key in batch {
+                    // Skip keys whose TTL was extended since the scan
+                    if expiry.get(key.as_str())?.is_some_and(|x| x.value() > now) {
+                        continue;
+                    }
+                    expiry.remove(key.as_str())?;
+                    ints.remove(key.as_str())?;
+                    floats.remove(key.as_str())?;
+                    strings.remove(key.as_str())?;
+                    removed += 1;
+                }
+            }
+            write_txn.commit()?;
+        }
+        Ok(removed)
+    }
+
+    /// Runs `purge_expired` every `interval` on a background thread until the
+    /// returned handle is dropped or the store itself is.
+    pub fn start_expiry_sweeper(self: &Arc<Self>, interval: Duration) -> SweeperHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let kv = Arc::downgrade(self);
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Some(kv) = kv.upgrade() else { break };
+                // A failed sweep is retried on the next tick
+                let _ = kv.purge_expired();
+            }
+        });
+        SweeperHandle { stop: Some(stop), thread: Some(thread) }
+    }
+
+    /// Stores `value` serialized as JSON in the string table.
+    pub fn write_json<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.write_string(key, &serde_json::to_string(value)?)?)
+    }
+
+    /// Reads a value stored by `write_json`.
+    pub fn read_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, Box<dyn std::error::Error>> {
+        match self.read_string(key)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the string under `key`, returning whether it existed and had not expired.
+    pub fn delete_string(&self, key: &str) -> Result<bool, Error> {
+        let write_txn = self.db.begin_write()?;
+        let existed = {
+            let mut table = write_txn.open_table(STRING_TABLE)?;
+            table.remove(key)?.is_some()
+        };
+        let existed = !take_expiry(&write_txn, key)? && existed;
+        write_txn.commit()?;
+        if existed {
+            self.notify(key, None);
+        }
+        Ok(existed)
+    }
+
+    /// Appends `suffix` to the string under `key` (treating a missing key as empty)
+    /// in one write transaction, so concurrent appenders never lose fragments.
+    /// redb has no in-place append: each call rewrites the whole value, so it costs
+    /// O(value length).
+    pub fn append_string(&self, key: &str, suffix: &str) -> Result<(), Error> {
+        self.remember_key(key);
+        let write_txn = self.db.begin_write()?;
+        // An expired value is gone, so the suffix starts a new one
+        let expired = take_expiry(&write_txn, key)?;
+        let value = {
+            let mut table = write_txn.open_table(STRING_TABLE)?;
+            let mut value = table.get(key)?.filter(|_| !expired).map(|x| x.value().to_string()).unwrap_or_default();
+            value.push_str(suffix);
+            table.insert(key, value.as_str())?;
+            value
+        };
+        write_txn.commit()?;
+        self.notify(key, Some(KvValue::String(value)));
+        Ok(())
+    }
+
+    /// Stores `value` under `key` and returns the value it replaced, in one write
+    /// transaction. An expired value counts as missing, and the TTL is cleared.
+    pub fn swap_int(&self, key: &str, value: i64) -> Result<Option<i64>, Error> {
+        self.remember_key(key);
+        let write_txn = self.db.begin_write()?;
+        let expired = take_expiry(&write_txn, key)?;
+        let previous = {
+            let mut table = write_txn.open_table(INT_TABLE)?;
+            table.insert(key, value)?.map(|x| x.value()).filter(|_| !expired)
+        };
+        write_txn.commit()?;
+        self.notify(key, Some(KvValue::Int(value)));
+        Ok(previous)
+    }
+
+    pub fn swap_float(&self, key: &str, value: f64) -> Result<Option<f64>, Error> {
+        self.remember_key(key);
+        let write_txn = self.db.begin_write()?;
+        let expired = take_expiry(&write_txn, key)?;
+        let previous = {
+            let mut table = write_txn.open_table(FLOAT_TABLE)?;
+            table.insert(key, value)?.map(|x| x.value()).filter(|_| !expired)
+        };
+        write_txn.commit()?;
+        self.notify(key, Some(KvValue::Float(value)));
+        Ok(previous)
+    }
+
+    pub fn swap_string(&self, key: &str, value: &str) -> Result<Option<String>, Error> {
+        self.remember_key(key);
+        let write_txn = self.db.begin_write()?;
+        let expired = take_expiry(&write_txn, key)?;
+        let previous = {
+            let mut table = write_txn.open_table(STRING_TABLE)?;
+            table.insert(key, value)?.map(|x| x.value().to_string()).filter(|_| !expired)
+        };
+        write_txn.commit()?;
+        self.notify(key, Some(KvValue::String(value.to_string())));
+        Ok(previous)
+    }
+
+    /// Returns the string under `key`, or runs `loader`, stores its result and returns it.
+    /// Loaders for the same key are serialized by a per-key lock: concurrent callers
+    /// wait for the first loader and then read its stored value instead of running
+    /// their own. Loaders for different keys run in parallel.
+    pub fn get_or_compute_string<E, F>(&self, key: &str, loader: F) -> Result<String, E>
+    where
+        E: From<Error>,
+        F: FnOnce() -> Result<String, E>,
+    {
+        if let Some(value) = self.read_string_or_none(key)? {
+            return Ok(value);
+        }
+
+        let key_lock = self.compute_locks.lock().unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let result = {
+            let _guard = key_lock.lock().unwrap();
+            match self.read_string_or_none(key)? {
+                Some(value) => Ok(value),
+                None => loader().and_then(|value| {
+                    self.write_string(key, &value)?;
+                    Ok(value)
+                }),
+            }
+        };
+
+        let mut locks = self.compute_locks.lock().unwrap();
+        // Only the map and this call still hold it: nobody else is waiting
+        if Arc::strong_count(&key_lock) == 2 {
+            locks.remove(key);
+        }
+        result
+    }
+
+    // Like `read_string`, but a store that has never held strings reads as empty
+    fn read_string_or_none(&self, key: &str) -> Result<Option<String>, Error> {
+        match self.read_string(key) {
+            Err(Error::TableDoesNotExist(_)) => Ok(None),
+            other => other,
+        }
+    }
+
+    /// Stores exactly `len` bytes from `reader` under `key`, replacing any previous blob.
+    /// Data is copied one chunk at a time so memory use stays bounded by the chunk size.
+    pub fn write_blob_stream(&self, key: &str, mut reader: impl Read, len: u64) -> Result<(), Error> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(BLOB_TABLE)?;
+            table.retain_in((key, 0)..=(key, u64::MAX), |_, _| false)?;
+
+            let mut buffer = vec![0; BLOB_CHUNK_SIZE];
+            let mut remaining = len;
+            let mut index = 0;
+            while remaining > 0 {
+                let chunk_len = remaining.min(BLOB_CHUNK_SIZE as u64) as usize;
+                reader.read_exact(&mut buffer[..chunk_len]).map_err(Error::Io)?;
+                table.insert((key, index), &buffer[..chunk_len])?;
+                remaining -= chunk_len as u64;
+                index += 1;
+            }
+            if index == 0 {
+                // Keep a marker chunk so an empty blob still reads back as present
+                table.insert((key, 0), &[][..])?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Copies the blob stored under `key` into `writer` chunk by chunk.
+    /// Returns the number of bytes written, or `None` if there is no such blob.
+    pub fn read_blob_stream(&self, key: &str, mut writer: impl Write) -> Result<Option<u64>, Error> {
+        let read_txn = self.begin_read()?;
+        let table = match read_txn.open_table(BLOB_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut written = None;
+        for chunk in table.range((key, 0)..=(key, u64::MAX))? {
+            let (_, value) = chunk?;
+            writer.write_all(value.value()).map_err(Error::Io)?;
+            *written.get_or_insert(0) += value.value().len() as u64;
+        }
+        writer.flush().map_err(Error::Io)?;
+        Ok(written)
+    }
+}
+
+fn add_table_keys<V: redb::Value + 'static>(
+    read_txn: &ReadTransaction,
+    definition: TableDefinition<&str, V>,
+    filter: &mut BloomFilter,
+) -> Result<(), Error> {
+    let table = match read_txn.open_table(definition) {
+        Ok(table) => table,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in table.iter()? {
+        filter.insert(entry?.0.value());
+    }
+    Ok(())
+}
+
+// Up to `limit` keys after `after` (exclusive), plus whether more follow
+fn table_keys<V: redb::Value + 'static>(
+    read_txn: &ReadTransaction,
+    definition: TableDefinition<&str, V>,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<(Vec<String>, bool), Error> {
+    let table = match read_txn.open_table(definition) {
+        Ok(table) => table,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok((Vec::new(), false)),
+        Err(e) => return Err(e.into()),
+    };
+    let range = match after {
+        Some(after) => table.range::<&str>((Bound::Excluded(after), Bound::Unbounded))?,
+        None => table.range::<&str>(..)?,
+    };
+    let expiry = open_expiry(read_txn)?;
+    let mut keys = Vec::new();
+    for entry in range {
+        let key = entry?.0;
+        if expiry.as_ref().map_or(Ok(false), |expiry| is_expired_in(expiry, key.value()))? {
+            continue;
+        }
+        if keys.len() == limit {
+            return Ok((keys, true));
+        }
+        keys.push(key.value().to_string());
+    }
+    Ok((keys, false))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+fn open_expiry(read_txn: &ReadTransaction) -> Result<Option<ReadOnlyTable<&'static str, u64>>, Error> {
+    match read_txn.open_table(EXPIRY_TABLE) {
+        Ok(table) => Ok(Some(table)),
+        Err(redb::TableError::TableDoesNotExist(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn is_expired_in(expiry: &impl ReadableTable<&'static str, u64>, key: &str) -> Result<bool, Error> {
+    Ok(expiry.get(key)?.is_some_and(|x| x.value() <= unix_millis(SystemTime::now())))
+}
+
+fn is_expired(read_txn: &ReadTransaction, key: &str) -> Result<bool, Error> {
+    match open_expiry(read_txn)? {
+        Some(expiry) => is_expired_in(&expiry, key),
+        None => Ok(false),
+    }
+}
+
+// Clears the TTL of `key` as part of a write to it, returning whether the value
+// it guarded had already expired and so should be treated as absent
+fn take_expiry(write_txn: &WriteTransaction, key: &str) -> Result<bool, Error> {
+    let mut expiry = write_txn.open_table(EXPIRY_TABLE)?;
+    Ok(expiry.remove(key)?.is_some_and(|x| x.value() <= unix_millis(SystemTime::now())))
+}
+
+fn table_contains<V: redb::Value + 'static>(
+    read_txn: &ReadTransaction,
+    definition: TableDefinition<&str, V>,
+    key: &str,
+) -> Result<bool, Error> {
+    match read_txn.open_table(definition) {
+        Ok(table) => Ok(table.get(key)?.is_some()),
+        Err(redb::TableError::TableDoesNotExist(_)) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub struct KvTransaction {
+    txn: WriteTransaction,
+    watchers: Arc<Mutex<Watchers>>,
+    bloom: Option<SharedBloomFilter>,
+    changes: Vec<(String, Option<KvValue>)>,
+}
+
+impl KvTransaction {
+    pub fn write_int(&mut self, key: &str, value: i64) -> Result<(), Error> {
+        self.remember_key(key);
+        self.txn.open_table(INT_TABLE)?.insert(key, value)?;
+        take_expiry(&self.txn, key)?;
+        self.changes.push((key.to_string(), Some(KvValue::Int(value))));
+        Ok(())
+    }
+
+    pub fn read_int(&self, key: &str) -> Result<Option<i64>, Error> {
+        let value = self.txn.open_table(INT_TABLE)?.get(key)?.map(|x| x.value());
+        self.unless_expired(key, value)
+    }
+
+    pub fn write_float(&mut self, key: &str, value: f64) -> Result<(), Error> {
+        self.remember_key(key);
+        self.txn.open_table(FLOAT_TABLE)?.insert(key, value)?;
+        take_expiry(&self.txn, key)?;
+        self.changes.push((key.to_string(), Some(KvValue::Float(value))));
+        Ok(())
+    }
+
+    pub fn read_float(&self, key: &str) -> Result<Option<f64>, Error> {
+        let value = self.txn.open_table(FLOAT_TABLE)?.get(key)?.map(|x| x.value());
+        self.unless_expired(key, value)
+    }
+
+    pub fn write_string(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.remember_key(key);
+        self.txn.open_table(STRING_TABLE)?.insert(key, value)?;
+        take_expiry(&self.txn, key)?;
+        self.changes.push((key.to_string(), Some(KvValue::String(value.to_string()))));
+        Ok(())
+    }
+
+    pub fn read_string(&self, key: &str) -> Result<Option<String>, Error> {
+        let value = self.txn.open_table(STRING_TABLE)?.get(key)?.map(|x| x.value().to_string());
+        self.unless_expired(key, value)
+    }
+
+    /// Like `KV::expire`, taking effect when the transaction commits.
+    pub fn expire(&mut self, key: &str, ttl: Duration) -> Result<(), Error> {
+        let expires_at = unix_millis(SystemTime::now() + ttl);
+        self.txn.open_table(EXPIRY_TABLE)?.insert(key, expires_at)?;
+        Ok(())
+    }
+
+    fn unless_expired<T>(&self, key: &str, value: Option<T>) -> Result<Option<T>, Error> {
+        if value.is_some() && is_expired_in(&self.txn.open_table(EXPIRY_TABLE)?, key)? {
+            return Ok(None);
+        }
+        Ok(value)
+    }
+
+    fn remember_key(&self, key: &str) {
+        if let Some(bloom) = &self.bloom {
+            bloom.write().unwrap().insert(key);
+        }
+    }
+
+    pub fn commit(self) -> Result<(), Error> {
+        self.txn.commit()?;
+        for (key, value) in self.changes {
+            notify_watchers(&self.watchers, &key, value);
+        }
+        Ok(())
+    }
+}
+
+/// Stops the expiry sweeper thread when dropped, waiting for a running sweep to finish.
+pub struct SweeperHandle {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for SweeperHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Snapshot {
+    txn: ReadTransaction,
+}
+
+impl Snapshot {
+    pub fn read_int(&self, key: &str) -> Result<Option<i64>, Error> {
+        let value = snapshot_get(&self.txn, INT_TABLE, key)?;
+        self.unless_expired(key, value)
+    }
+
+    pub fn read_float(&self, key: &str) -> Result<Option<f64>, Error> {
+        let value = snapshot_get(&self.txn, FLOAT_TABLE, key)?;
+        self.unless_expired(key, value)
+    }
+
+    pub fn read_string(&self, key: &str) -> Result<Option<String>, Error> {
+        let table = match self.txn.open_table(STRING_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let value = table.get(key)?.map(|x| x.value().to_string());
+        self.unless_expired(key, value)
+    }
+
+    // TTLs are measured against the current time, so a value can expire while the snapshot is held
+    fn unless_expired<T>(&self, key: &str, value: Option<T>) -> Result<Option<T>, Error> {
+        if value.is_some() && is_expired(&self.txn, key)? {
+            return Ok(None);
+        }
+        Ok(value)
+    }
+}
+
+// Reads a `Copy`-like value whose borrowed form is itself (i64, f64)
+fn snapshot_get<V>(
+    read_txn: &ReadTransaction,
+    definition: TableDefinition<&str, V>,
+    key: &str,
+) -> Result<Option<V>, Error>
+where
+    V: for<'a> redb::Value<SelfType<'a> = V> + 'static,
+{
+    let table = match read_txn.open_table(definition) {
+        Ok(table) => table,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(table.get(key)?.map(|x| x.value()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{peak_live_bytes, temp_kv, TempPath};
+
+    #[test]
+    fn watch_key_fires_only_for_the_watched_key() {
+        let (kv, _path) = temp_kv();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let _guard = kv.watch_key("watched", move |event| seen.lock().unwrap().push(event.clone()));
+
+        kv.write_string("other", "x").unwrap();
+        kv.write_string("watched", "a").unwrap();
+        kv.write_int("watched", 7).unwrap();
+        kv.delete_string("watched").unwrap();
+
+        let events = events.lock().unwrap();
+        let values: Vec<_> = events.iter().map(|event| event.value.clone()).collect();
+        assert_eq!(values, vec![Some(KvValue::String("a".into())), Some(KvValue::Int(7)), None]);
+        assert!(events.iter().all(|event| event.key == "watched"));
+    }
+
+    #[test]
+    fn watcher_may_write_its_own_key() {
+        let (kv, _path) = temp_kv();
+        let kv = Arc::new(kv);
+        let store = Arc::downgrade(&kv);
+        let _guard = kv.watch_key("counter", move |event| {
+            // Writing the watched key again re-enters this callback
+            if let Some(KvValue::Int(n)) = event.value && n < 3 {
+                store.upgrade().unwrap().write_int("counter", n + 1).unwrap();
+            }
+        });
+        kv.write_int("counter", 0).unwrap();
+        assert_eq!(kv.read_int("counter").unwrap(), Some(3));
+    }
+
+    #[test]
+    fn dropping_the_guard_stops_notifications() {
+        let (kv, _path) = temp_kv();
+        let count = Arc::new(AtomicU64::new(0));
+        let seen = count.clone();
+        let guard = kv.watch_key("k", move |_| {
+            seen.fetch_add(1, Ordering::Relaxed);
+        });
+        kv.write_string("k", "1").unwrap();
+        drop(guard);
+        kv.write_string("k", "2").unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    // A repeatable byte pattern, so a blob can be checked without keeping a copy
+    fn pattern_byte(offset: u64) -> u8 {
+        (offset ^ (offset >> 11) ^ (offset >> 23)) as u8
+    }
+
+    struct PatternReader(u64);
+
+    impl Read for PatternReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            for byte in buf.iter_mut() {
+                *byte = pattern_byte(self.0);
+                self.0 += 1;
+            }
+            Ok(buf.len())
+        }
     }
-}
\ No newline at end of file
+
+    struct PatternChecker(u64);
+
+    impl Write for PatternChecker {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            for &byte in buf {
+                assert_eq!(byte, pattern_byte(self.0), "mismatch at offset {}", self.0);
+                self.0 += 1;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn blob_streams_round_trip_in_bounded_memory() {
+        const LEN: u64 = 256 * 1024 * 1024;
+        const CACHE: usize = 4 * BLOB_CHUNK_SIZE;
+        // The page cache and a few chunks in flight, far below the blob itself
+        const BOUND: usize = CACHE + 8 * BLOB_CHUNK_SIZE;
+        let path = TempPath::new("blobs.redb");
+        let kv = KV::open_with_cache_size(&*path, CACHE).unwrap();
+
+        let (written, peak) = peak_live_bytes(|| kv.write_blob_stream("blob", PatternReader(0), LEN));
+        written.unwrap();
+        assert!(peak < BOUND, "write held {} bytes at once", peak);
+
+        let mut checker = PatternChecker(0);
+        let reads = kv.stats().read_transactions;
+        let (read, peak) = peak_live_bytes(|| kv.read_blob_stream("blob", &mut checker));
+        assert_eq!(read.unwrap(), Some(LEN));
+        assert_eq!(checker.0, LEN);
+        assert!(peak < BOUND, "read held {} bytes at once", peak);
+        assert_eq!(kv.stats().read_transactions, reads + 1);
+    }
+
+    #[test]
+    fn empty_and_missing_blobs_differ() {
+        let (kv, _path) = temp_kv();
+        assert_eq!(kv.read_blob_stream("blob", std::io::sink()).unwrap(), None);
+        kv.write_blob_stream("blob", std::io::empty(), 0).unwrap();
+        assert_eq!(kv.read_blob_stream("blob", std::io::sink()).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn bloom_filter_skips_absent_keys_without_a_transaction() {
+        let (kv, path) = temp_kv();
+        kv.write_int("present", 1).unwrap();
+        kv.write_string("greeting", "hi").unwrap();
+        drop(kv);
+
+        let kv = KV::open_with_bloom_filter(&*path, 1000, 0.01).unwrap();
+        let before = kv.stats();
+        assert_eq!(kv.read_int("absent").unwrap(), None);
+        assert!(!kv.exists("absent").unwrap());
+        let after = kv.stats();
+        assert_eq!(after.bloom_filter_skips, before.bloom_filter_skips + 2);
+        assert_eq!(after.read_transactions, before.read_transactions);
+
+        assert_eq!(kv.read_int("present").unwrap(), Some(1));
+        assert_eq!(kv.read_string("greeting").unwrap().as_deref(), Some("hi"));
+        kv.write_int("later", 2).unwrap();
+        assert_eq!(kv.read_int("later").unwrap(), Some(2));
+        assert_eq!(kv.stats().bloom_filter_skips, after.bloom_filter_skips);
+    }
+
+    #[test]
+    fn concurrent_appends_all_land() {
+        let (kv, _path) = temp_kv();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let kv = &kv;
+                scope.spawn(move || {
+                    for n in 0..50 {
+                        kv.append_string("log", &format!("{}-{};", thread, n)).unwrap();
+                    }
+                });
+            }
+        });
+        let log = kv.read_string("log").unwrap().unwrap();
+        let mut fragments: Vec<_> = log.split_terminator(';').collect();
+        assert_eq!(fragments.len(), 8 * 50);
+        fragments.sort();
+        fragments.dedup();
+        assert_eq!(fragments.len(), 8 * 50);
+    }
+
+    #[test]
+    fn get_or_compute_loads_once() {
+        let (kv, path) = temp_kv();
+        let mut loads = 0;
+        let value = kv.get_or_compute_string::<Error, _>("profile", || {
+            loads += 1;
+            Ok("loaded".to_string())
+        }).unwrap();
+        assert_eq!(value, "loaded");
+        let value = kv.get_or_compute_string::<Error, _>("profile", || {
+            loads += 1;
+            Ok("reloaded".to_string())
+        }).unwrap();
+        assert_eq!(value, "loaded");
+        assert_eq!(loads, 1);
+
+        drop(kv);
+        let kv = KV::open(&*path).unwrap();
+        assert_eq!(kv.read_string("profile").unwrap().as_deref(), Some("loaded"));
+    }
+
+    #[test]
+    fn concurrent_callers_share_one_load() {
+        let (kv, _path) = temp_kv();
+        let loads = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let value = kv.get_or_compute_string::<Error, _>("slow", || {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(50));
+                        Ok("value".to_string())
+                    }).unwrap();
+                    assert_eq!(value, "value");
+                });
+            }
+        });
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn describe_reports_written_tables() {
+        let (kv, _path) = temp_kv();
+        kv.write_int("a", 1).unwrap();
+        kv.write_int("b", 2).unwrap();
+        kv.write_string("greeting", "hello").unwrap();
+
+        let info = kv.describe().unwrap();
+        assert_eq!(info.schema_version, SCHEMA_VERSION);
+        assert!(info.file_size > 0);
+        let table = |name: &str| info.tables.iter().find(|table| table.name == name)
+            .unwrap_or_else(|| panic!("no {} table in {:?}", name, info.tables));
+        assert_eq!(table("integers").entries, 2);
+        assert_eq!(table("strings").entries, 1);
+        assert!(table("strings").stored_bytes >= "greeting".len() as u64 + "hello".len() as u64);
+        assert!(!info.tables.iter().any(|table| table.name == "floats"));
+    }
+
+    #[test]
+    fn swap_returns_the_previous_value() {
+        let (kv, _path) = temp_kv();
+        assert_eq!(kv.swap_int("n", 1).unwrap(), None);
+        assert_eq!(kv.read_int("n").unwrap(), Some(1));
+        assert_eq!(kv.swap_int("n", 2).unwrap(), Some(1));
+        assert_eq!(kv.read_int("n").unwrap(), Some(2));
+
+        assert_eq!(kv.swap_float("f", 0.5).unwrap(), None);
+        assert_eq!(kv.swap_float("f", 1.5).unwrap(), Some(0.5));
+
+        assert_eq!(kv.swap_string("s", "old").unwrap(), None);
+        assert_eq!(kv.swap_string("s", "new").unwrap().as_deref(), Some("old"));
+        assert_eq!(kv.read_string("s").unwrap().as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn pages_cover_every_key_once() {
+        let (kv, _path) = temp_kv();
+        for n in 0..25 {
+            kv.write_int(&format!("key{:02}", n), n).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut pages = 0;
+        let mut cursor = None;
+        loop {
+            let (keys, next) = kv.list_keys_paginated(ValueType::Int, cursor.as_deref(), 10).unwrap();
+            pages += 1;
+            seen.extend(keys);
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        let expected: Vec<_> = (0..25).map(|n| format!("key{:02}", n)).collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn snapshot_ignores_later_writes() {
+        let (kv, _path) = temp_kv();
+        kv.write_int("balance", 10).unwrap();
+        kv.write_string("owner", "ann").unwrap();
+        let snapshot = kv.snapshot().unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                kv.write_int("balance", 20).unwrap();
+                kv.write_string("owner", "bob").unwrap();
+                kv.write_float("rate", 1.5).unwrap();
+            });
+        });
+
+        assert_eq!(snapshot.read_int("balance").unwrap(), Some(10));
+        assert_eq!(snapshot.read_string("owner").unwrap().as_deref(), Some("ann"));
+        assert_eq!(snapshot.read_float("rate").unwrap(), None);
+        assert_eq!(kv.read_int("balance").unwrap(), Some(20));
+        assert_eq!(kv.read_float("rate").unwrap(), Some(1.5));
+    }
+
+    #[test]
+    fn a_rewrite_clears_the_ttl() {
+        let (kv, _path) = temp_kv();
+        kv.write_string("session", "old").unwrap();
+        kv.expire("session", Duration::from_millis(20)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(kv.read_string("session").unwrap(), None);
+
+        kv.write_string("session", "new").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(kv.read_string("session").unwrap().as_deref(), Some("new"));
+        assert_eq!(kv.purge_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn expired_keys_are_missing_from_every_read_path() {
+        let (kv, _path) = temp_kv();
+        kv.write_string("user:1", "ann").unwrap();
+        kv.write_string("user:2", "bob").unwrap();
+        kv.write_int("hits", 5).unwrap();
+        kv.expire("user:1", Duration::from_millis(20)).unwrap();
+        kv.expire("hits", Duration::from_millis(20)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert!(!kv.exists("user:1").unwrap());
+        assert!(kv.exists("user:2").unwrap());
+        assert_eq!(kv.scan_prefix_string("user:").unwrap(), vec![("user:2".to_string(), "bob".to_string())]);
+        assert_eq!(kv.list_keys(ValueType::String).unwrap(), vec!["user:2".to_string()]);
+
+        let snapshot = kv.snapshot().unwrap();
+        assert_eq!(snapshot.read_string("user:1").unwrap(), None);
+        assert_eq!(snapshot.read_int("hits").unwrap(), None);
+        drop(snapshot);
+
+        kv.transaction(|tx| {
+            assert_eq!(tx.read_string("user:1")?, None);
+            assert_eq!(tx.read_int("hits")?, None);
+            Ok::<_, Error>(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn a_ttl_set_in_a_transaction_commits_with_the_value() {
+        let (kv, _path) = temp_kv();
+        kv.write_string("other", "kept").unwrap();
+        let mut tx = kv.begin_transaction().unwrap();
+        tx.write_string("token", "abc").unwrap();
+        tx.expire("token", Duration::ZERO).unwrap();
+        drop(tx);
+        assert_eq!(kv.read_string("token").unwrap(), None);
+        assert_eq!(kv.purge_expired().unwrap(), 0);
+
+        kv.transaction(|tx| {
+            tx.write_string("token", "abc")?;
+            tx.expire("token", Duration::ZERO)
+        }).unwrap();
+        assert_eq!(kv.read_string("token").unwrap(), None);
+        assert_eq!(kv.purge_expired().unwrap(), 1);
+        assert_eq!(kv.read_string("other").unwrap().as_deref(), Some("kept"));
+    }
+
+    #[test]
+    fn writes_over_an_expired_value_start_fresh() {
+        let (kv, _path) = temp_kv();
+        kv.write_int("n", 1).unwrap();
+        kv.write_string("log", "old;").unwrap();
+        kv.write_string("gone", "x").unwrap();
+        for key in ["n", "log", "gone"] {
+            kv.expire(key, Duration::from_millis(20)).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(kv.swap_int("n", 2).unwrap(), None);
+        kv.append_string("log", "new;").unwrap();
+        assert!(!kv.delete_string("gone").unwrap());
+        assert_eq!(kv.read_int("n").unwrap(), Some(2));
+        assert_eq!(kv.read_string("log").unwrap().as_deref(), Some("new;"));
+        assert_eq!(kv.purge_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn the_sweeper_purges_without_a_manual_call() {
+        let (kv, _path) = temp_kv();
+        let kv = Arc::new(kv);
+        kv.write_string("temp", "x").unwrap();
+        kv.expire("temp", Duration::from_millis(10)).unwrap();
+        let _sweeper = kv.start_expiry_sweeper(Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(kv.purge_expired().unwrap(), 0);
+        assert_eq!(kv.read_string("temp").unwrap(), None);
+    }
+}
@@ -1,28 +1,338 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::{Duration, Instant};
-use mlua::{Lua, Function, UserData, FromLua};
+use mlua::{Lua, Function, UserData, UserDataMethods, FromLua, HookTriggers, DebugEvent};
 use std::path::Path;
 use std::result::Result;
+use crate::core::kv::{KV, KvTransaction};
+use crate::core::net::{decode_text, expand_url_template, HttpClient, HttpResponse};
+use crate::core::{http_cache, queue, session};
+use tokio::runtime::Handle;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LuaErrorKind {
+    Syntax,
+    Runtime,
+    Memory,
+    Other,
+}
+
+#[derive(Clone, Debug)]
+pub struct LuaError {
+    pub kind: LuaErrorKind,
+    pub message: String,
+}
+
+impl LuaErrorKind {
+    fn of(e: &mlua::Error) -> Self {
+        match e {
+            mlua::Error::SyntaxError { .. } => LuaErrorKind::Syntax,
+            mlua::Error::RuntimeError(_) => LuaErrorKind::Runtime,
+            mlua::Error::MemoryError(_) => LuaErrorKind::Memory,
+            mlua::Error::CallbackError { cause, .. } => Self::of(cause),
+            mlua::Error::WithContext { cause, .. } => Self::of(cause),
+            _ => LuaErrorKind::Other,
+        }
+    }
+}
+
+impl From<mlua::Error> for LuaError {
+    fn from(e: mlua::Error) -> Self {
+        LuaError {
+            kind: LuaErrorKind::of(&e),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LuaError {}
+
+/// Result of `LuaBridge::try_call`, which never propagates Lua errors as `Err`.
+pub enum CallOutcome<'lua> {
+    Ok(Vec<mlua::Value<'lua>>),
+    Err { message: String, traceback: String },
+}
+
+impl CallOutcome<'_> {
+    fn from_error(e: mlua::Error) -> Self {
+        // mlua invokes Lua under a traceback message handler, which appends the
+        // stack to string errors and records it separately for Rust callbacks
+        let (message, traceback) = match e {
+            mlua::Error::CallbackError { traceback, cause } => (cause.to_string(), traceback),
+            mlua::Error::RuntimeError(msg) => match msg.split_once("\nstack traceback:") {
+                Some((message, stack)) => (message.to_string(), format!("stack traceback:{}", stack)),
+                None => (msg, String::new()),
+            },
+            e => (e.to_string(), String::new()),
+        };
+        CallOutcome::Err { message, traceback }
+    }
+}
 
 #[derive(Clone)]
-struct TimerHandle(usize);
+struct TimerHandle {
+    id: usize,
+    timers: Arc<Mutex<TimerState>>,
+}
+
+// Timers expiring in the same poll fire highest priority first, then earliest deadline
+const DEFAULT_TIMER_PRIORITY: i64 = 0;
 
 struct TimerEntry {
     end_time: Instant,
     callback: String,  // Store function name instead of Function
+    priority: i64,
+}
+
+impl UserData for TimerHandle {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(mlua::MetaMethod::Eq, |_, this, other: mlua::AnyUserData| {
+            Ok(other.borrow::<TimerHandle>().is_ok_and(|other| other.id == this.id))
+        });
+        methods.add_method("isActive", |_, this, ()| {
+            Ok(this.timers.lock().unwrap().active_timers.contains_key(&this.id))
+        });
+        // Seconds until the timer fires, or nil once it has fired or been removed
+        methods.add_method("remaining", |_, this, ()| {
+            let state = this.timers.lock().unwrap();
+            Ok(state.active_timers.get(&this.id)
+                .map(|entry| entry.end_time.saturating_duration_since(Instant::now()).as_secs_f64()))
+        });
+    }
 }
 
-impl UserData for TimerHandle {}
+// Handed to `kv.transaction` callbacks; emptied once the transaction ends
+struct LuaKvTransaction(Arc<Mutex<Option<KvTransaction>>>);
+
+impl LuaKvTransaction {
+    fn with<R>(&self, f: impl FnOnce(&mut KvTransaction) -> Result<R, redb::Error>) -> mlua::Result<R> {
+        let mut guard = self.0.lock().unwrap();
+        let tx = guard.as_mut()
+            .ok_or_else(|| mlua::Error::RuntimeError("KV transaction is closed".to_string()))?;
+        f(tx).map_err(mlua::Error::external)
+    }
+}
+
+impl UserData for LuaKvTransaction {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("getInt", |_, this, key: String| this.with(|tx| tx.read_int(&key)));
+        methods.add_method("setInt", |_, this, (key, value): (String, i64)| {
+            this.with(|tx| tx.write_int(&key, value))
+        });
+        methods.add_method("getFloat", |_, this, key: String| this.with(|tx| tx.read_float(&key)));
+        methods.add_method("setFloat", |_, this, (key, value): (String, f64)| {
+            this.with(|tx| tx.write_float(&key, value))
+        });
+        methods.add_method("getString", |_, this, key: String| this.with(|tx| tx.read_string(&key)));
+        methods.add_method("setString", |_, this, (key, value): (String, String)| {
+            this.with(|tx| tx.write_string(&key, &value))
+        });
+    }
+}
+
+// Keys the crate keeps for itself, which `kv.keys` and `kv.scan` leave out
+const INTERNAL_KEY_PREFIXES: &[&str] = &[
+    session::KEY_PREFIX,
+    queue::KEY_PREFIX,
+    http_cache::KEY_PREFIX,
+];
+
+// String entries under `prefix`, without the crate's internal ones
+fn script_entries(kv: &KV, prefix: Option<&str>) -> mlua::Result<Vec<(String, String)>> {
+    let mut entries = kv.scan_prefix_string(prefix.unwrap_or("")).map_err(mlua::Error::external)?;
+    entries.retain(|(key, _)| !INTERNAL_KEY_PREFIXES.iter().any(|internal| key.starts_with(internal)));
+    Ok(entries)
+}
+
+type TimerErrorHandler = Arc<dyn Fn(&str, &LuaError) + Send + Sync>;
+
+// How long lock(name) blocks outside a coroutine before raising
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Named, reentrant locks for scripts. Each bridge has its own set unless one is
+/// shared through `LuaBridge::share_locks`, as `LuaBridgePool` does.
+#[derive(Default)]
+pub struct NamedLocks {
+    held: Mutex<HashMap<String, LockHold>>,
+    released: Condvar,
+}
+
+// Locks are owned by a Lua thread (coroutine), which belongs to the Lua state of one bridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct LockOwner {
+    state: usize,
+    thread: usize,
+}
+
+#[derive(Clone, Copy)]
+struct LockHold {
+    owner: LockOwner,
+    count: usize,
+}
+
+impl NamedLocks {
+    fn try_lock(&self, name: &str, owner: LockOwner) -> bool {
+        Self::take(&mut self.held.lock().unwrap(), name, owner)
+    }
+
+    fn lock_timeout(&self, name: &str, owner: LockOwner, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut held = self.held.lock().unwrap();
+        while !Self::take(&mut held, name, owner) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            held = self.released.wait_timeout(held, remaining).unwrap().0;
+        }
+        true
+    }
+
+    fn take(held: &mut HashMap<String, LockHold>, name: &str, owner: LockOwner) -> bool {
+        match held.get_mut(name) {
+            Some(hold) if hold.owner == owner => {
+                hold.count += 1;
+                true
+            }
+            Some(_) => false,
+            None => {
+                held.insert(name.to_string(), LockHold { owner, count: 1 });
+                true
+            }
+        }
+    }
+
+    fn unlock(&self, name: &str, owner: LockOwner) -> bool {
+        let mut held = self.held.lock().unwrap();
+        match held.get_mut(name) {
+            Some(hold) if hold.owner == owner => {
+                hold.count -= 1;
+                if hold.count == 0 {
+                    held.remove(name);
+                    self.released.notify_all();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // The locks held by threads of the Lua state `state`
+    fn held_by(&self, state: usize) -> HashMap<String, LockHold> {
+        self.held.lock().unwrap().iter()
+            .filter(|(_, hold)| hold.owner.state == state)
+            .map(|(name, hold)| (name.clone(), *hold))
+            .collect()
+    }
+
+    // Undoes the holds `state` took since `before` was read, keeping older ones
+    fn release_since(&self, state: usize, before: &HashMap<String, LockHold>) {
+        let mut held = self.held.lock().unwrap();
+        let mut released = false;
+        held.retain(|name, hold| {
+            if hold.owner.state != state {
+                return true;
+            }
+            match before.get(name) {
+                Some(earlier) if earlier.owner == hold.owner => {
+                    hold.count = hold.count.min(earlier.count);
+                    true
+                }
+                _ => {
+                    released = true;
+                    false
+                }
+            }
+        });
+        if released {
+            self.released.notify_all();
+        }
+    }
+}
+
+// The locks a bridge's scripts held before a call, to release what a failed call took
+struct LockCheckpoint {
+    locks: Arc<NamedLocks>,
+    state: usize,
+    held: HashMap<String, LockHold>,
+}
+
+impl LockCheckpoint {
+    fn release_on_error<T, E>(self, result: Result<T, E>) -> Result<T, E> {
+        if result.is_err() {
+            self.locks.release_since(self.state, &self.held);
+        }
+        result
+    }
+}
 
 struct TimerState {
     next_id: usize,
     active_timers: HashMap<usize, TimerEntry>,
+    deferred: VecDeque<String>,
+    error_handler: Option<TimerErrorHandler>,
+}
+
+struct ProfileFrame {
+    name: String,
+    start: Instant,
+    // Inclusive time of the calls made from this frame
+    children: Duration,
+}
+
+#[derive(Default)]
+struct ProfileStats {
+    total: Duration,
+    exclusive: Duration,
+    calls: u64,
+}
+
+#[derive(Default)]
+struct ProfilerState {
+    stack: Vec<ProfileFrame>,
+    stats: HashMap<String, ProfileStats>,
+}
+
+impl ProfilerState {
+    fn enter(&mut self, name: String) {
+        self.stack.push(ProfileFrame { name, start: Instant::now(), children: Duration::ZERO });
+    }
+
+    fn leave(&mut self) {
+        // Returns from frames entered before profiling started have nothing to pop
+        let Some(frame) = self.stack.pop() else { return };
+        let elapsed = frame.start.elapsed();
+        let stats = self.stats.entry(frame.name).or_default();
+        stats.total += elapsed;
+        stats.exclusive += elapsed.saturating_sub(frame.children);
+        stats.calls += 1;
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+
+    fn report(&self, time: impl Fn(&ProfileStats) -> Duration) -> Vec<(String, Duration, u64)> {
+        let mut report: Vec<_> = self.stats.iter()
+            .map(|(name, stats)| (name.clone(), time(stats), stats.calls))
+            .collect();
+        report.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        report
+    }
 }
 
 pub struct LuaBridge {
     lua: Lua,
     timers: Arc<Mutex<TimerState>>,
+    profiler: Arc<Mutex<ProfilerState>>,
+    locks: Mutex<Arc<NamedLocks>>,
 }
 
 impl LuaBridge {
@@ -31,20 +341,26 @@ impl LuaBridge {
         let timers = Arc::new(Mutex::new(TimerState {
             next_id: 1,
             active_timers: HashMap::new(),
+            deferred: VecDeque::new(),
+            error_handler: None,
         }));
 
-        let bridge = LuaBridge { lua, timers };
+        let bridge = LuaBridge { lua, timers, profiler: Arc::default(), locks: Mutex::default() };
         bridge.init_timer_api()?;
+        bridge.init_validation_api().map_err(|e| e.to_string())?;
+        bridge.share_locks(Arc::new(NamedLocks::default()))?;
         Ok(bridge)
     }
 
     fn init_timer_api(&self) -> Result<(), String> {
         let timers_add = self.timers.clone();
         
+        // addTimer({delaySeconds, callbackName[, priority]})
         self.export_function("addTimer", move |lua, value: mlua::Value| {
             let table = mlua::Table::from_lua(value, lua)?;
             let delay: f64 = table.get(1)?;
             let callback_name: String = table.get(2)?;
+            let priority: Option<i64> = table.get(3)?;
             
             let handle = {
                 let mut state = timers_add.lock().unwrap();
@@ -53,8 +369,9 @@ impl LuaBridge {
                 state.active_timers.insert(id, TimerEntry {
                     end_time: Instant::now() + Duration::from_secs_f64(delay),
                     callback: callback_name,  // Store function name
+                    priority: priority.unwrap_or(DEFAULT_TIMER_PRIORITY),
                 });
-                TimerHandle(id)
+                TimerHandle { id, timers: timers_add.clone() }
             };
             Ok(handle)
         }).map_err(|e| e.to_string())?;
@@ -65,62 +382,418 @@ impl LuaBridge {
             let now = Instant::now();
             let mut expired = Vec::new();
             
-            state.active_timers.retain(|id, entry| {
+            state.active_timers.retain(|_, entry| {
                 if entry.end_time <= now {
-                    expired.push((*id, entry.callback.clone()));
+                    expired.push((entry.priority, entry.end_time, entry.callback.clone()));
                     false
                 } else {
                     true
                 }
             });
+            expired.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+            // Ticks queued by the callbacks below wait for the next poll
+            let deferred = std::mem::take(&mut state.deferred);
+            // Release the lock so callbacks can add or remove timers
+            drop(state);
             
-            // Look up and call functions by name
-            for (_, func_name) in expired {
-                let func: Function = lua.globals().get(&*func_name)?;  // Added dereference here
-                func.call::<_, ()>(())?;
+            // Look up and call functions by name, deferred ticks first. A failing
+            // callback must not keep the others from running
+            let callbacks = deferred.into_iter().chain(expired.into_iter().map(|(_, _, name)| name));
+            let mut first_error = None;
+            for func_name in callbacks {
+                let (error, kind) = match lua.globals().get::<_, mlua::Value>(&*func_name) {
+                    Ok(mlua::Value::Function(func)) => match func.call::<_, ()>(()) {
+                        Ok(()) => continue,
+                        Err(e) => (e, None),
+                    },
+                    // The global was deleted or reassigned since the timer was added
+                    Ok(other) => (mlua::Error::RuntimeError(
+                        format!("timer callback '{}' is not a function (got {})", func_name, other.type_name())
+                    ), Some(LuaErrorKind::Other)),
+                    Err(e) => (e, None),
+                };
+                let handler = timers_poll.lock().unwrap().error_handler.clone();
+                match handler {
+                    Some(handler) => {
+                        let mut error = LuaError::from(error);
+                        error.kind = kind.unwrap_or(error.kind);
+                        handler(&func_name, &error);
+                    }
+                    None => {
+                        first_error.get_or_insert(error);
+                    }
+                }
             }
-            Ok(())
+            first_error.map_or(Ok(()), Err)
         }).map_err(|e| e.to_string())?;
     
+        // nextTick(callbackName) runs the callback at the start of the next pollTimers
+        let timers_defer = self.timers.clone();
+        self.export_function("nextTick", move |lua, value: mlua::Value| {
+            let callback_name = String::from_lua(value, lua)?;
+            timers_defer.lock().unwrap().deferred.push_back(callback_name);
+            Ok(())
+        }).map_err(|e| e.to_string())?;
+
         let timers_remove = self.timers.clone();
         self.export_function("removeTimer", move |lua, value: mlua::Value| {
             let ud = mlua::AnyUserData::from_lua(value, lua)?;
             let handle = ud.borrow::<TimerHandle>()?.clone();
             let mut state = timers_remove.lock().unwrap();
-            state.active_timers.remove(&handle.0);
+            state.active_timers.remove(&handle.id);
             Ok(())
         }).map_err(|e| e.to_string())?;
     
         Ok(())
     }
 
-    pub fn load_file(&self, path: &str) -> Result<(), String> {
+    // Validation failures raise "[E_TYPE] ...", "[E_RANGE] ..." or "[E_FIELD] ..."
+    // so error handlers can categorize them by the bracketed code
+    fn init_validation_api(&self) -> mlua::Result<()> {
+        let globals = self.lua.globals();
+
+        // assertType(value, typeName) -> value
+        globals.set("assertType", self.lua.create_function(|_, (value, expected): (mlua::Value, String)| {
+            let actual = lua_type_name(&value);
+            if actual != expected {
+                return Err(mlua::Error::RuntimeError(
+                    format!("[E_TYPE] expected {}, got {}", expected, actual)
+                ));
+            }
+            Ok(value)
+        })?)?;
+
+        // assertRange(n, min, max) -> n, bounds inclusive
+        globals.set("assertRange", self.lua.create_function(|_, (n, min, max): (mlua::Value, f64, f64)| {
+            let x = match n {
+                mlua::Value::Integer(i) => i as f64,
+                mlua::Value::Number(f) => f,
+                ref other => return Err(mlua::Error::RuntimeError(
+                    format!("[E_TYPE] expected number, got {}", lua_type_name(other))
+                )),
+            };
+            if !(min..=max).contains(&x) {
+                return Err(mlua::Error::RuntimeError(
+                    format!("[E_RANGE] expected {} in [{}, {}]", x, min, max)
+                ));
+            }
+            Ok(n)
+        })?)?;
+
+        // require_field(table, name) -> table[name]
+        globals.set("require_field", self.lua.create_function(|_, (table, field): (mlua::Table, String)| {
+            let value: mlua::Value = table.get(field.as_str())?;
+            if value.is_nil() {
+                return Err(mlua::Error::RuntimeError(
+                    format!("[E_FIELD] missing required field '{}'", field)
+                ));
+            }
+            Ok(value)
+        })?)?;
+
+        Ok(())
+    }
+
+    /// Binds `lock(name[, timeoutSeconds])`, `tryLock(name)` and `unlock(name)` to
+    /// `locks`. Locks are owned by the calling coroutine and are reentrant, so each
+    /// `lock` needs a matching `unlock`. Inside a coroutine a contended `lock` yields
+    /// until it succeeds, leaving the script's scheduler to resume it; elsewhere it
+    /// blocks and raises "[E_LOCK] ..." after the timeout (10 seconds by default).
+    /// When `load_string`, `load_file`, `call_function` or `try_call` fails, the
+    /// locks taken during that call are released; locks held from earlier calls stay.
+    pub fn share_locks(&self, locks: Arc<NamedLocks>) -> Result<(), String> {
+        self.init_lock_api(locks.clone()).map_err(|e| e.to_string())?;
+        *self.locks.lock().unwrap() = locks;
+        Ok(())
+    }
+
+    // Identifies this bridge's Lua state; calls from Rust always run on the main thread
+    fn lock_state(&self) -> usize {
+        self.lua.current_thread().to_pointer() as usize
+    }
+
+    fn lock_checkpoint(&self) -> LockCheckpoint {
+        let locks = self.locks.lock().unwrap().clone();
+        let state = self.lock_state();
+        let held = locks.held_by(state);
+        LockCheckpoint { locks, state, held }
+    }
+
+    fn init_lock_api(&self, locks: Arc<NamedLocks>) -> mlua::Result<()> {
+        let globals = self.lua.globals();
+        let state = self.lock_state();
+        let owner = move |lua: &Lua| LockOwner { state, thread: lua.current_thread().to_pointer() as usize };
+
+        let registry = locks.clone();
+        let try_lock = self.lua.create_function(move |lua, name: String| {
+            Ok(registry.try_lock(&name, owner(lua)))
+        })?;
+        let registry = locks.clone();
+        let lock_blocking = self.lua.create_function(move |lua, (name, timeout): (String, Option<f64>)| {
+            let timeout = timeout.map_or(DEFAULT_LOCK_TIMEOUT, Duration::from_secs_f64);
+            if !registry.lock_timeout(&name, owner(lua), timeout) {
+                return Err(mlua::Error::RuntimeError(
+                    format!("[E_LOCK] timed out waiting for lock '{}'", name)
+                ));
+            }
+            Ok(())
+        })?;
+        let registry = locks;
+        globals.set("unlock", self.lua.create_function(move |lua, name: String| {
+            if !registry.unlock(&name, owner(lua)) {
+                return Err(mlua::Error::RuntimeError(
+                    format!("[E_LOCK] lock '{}' is not held by this coroutine", name)
+                ));
+            }
+            Ok(())
+        })?)?;
+
+        // Yielding has to happen on the Lua side, so lock itself is a Lua closure
+        let lock: Function = self.lua.load(r#"
+            local tryLock, lockBlocking = ...
+            return function(name, timeout)
+                if coroutine.isyieldable() then
+                    while not tryLock(name) do coroutine.yield() end
+                else
+                    lockBlocking(name, timeout)
+                end
+            end
+        "#).call((try_lock.clone(), lock_blocking))?;
+        globals.set("lock", lock)?;
+        globals.set("tryLock", try_lock)
+    }
+
+    /// Called with the callback name when a timer or `nextTick` callback fails in
+    /// `pollTimers`, either by raising an error or because its global no longer
+    /// holds a function. The poll goes on with the remaining callbacks either way;
+    /// without a handler it then raises the first error.
+    pub fn set_timer_error_handler<F>(&self, handler: F)
+    where
+        F: Fn(&str, &LuaError) + Send + Sync + 'static,
+    {
+        self.timers.lock().unwrap().error_handler = Some(Arc::new(handler));
+    }
+
+    /// Starts timing every Lua and C function call, discarding earlier results.
+    /// Functions are named as Lua reports them, or `source:line` when anonymous.
+    pub fn enable_profiler(&self) {
+        *self.profiler.lock().unwrap() = ProfilerState::default();
+        let profiler = self.profiler.clone();
+        let triggers = HookTriggers::new().on_calls().on_returns();
+        self.lua.set_hook(triggers, move |_, debug| {
+            let mut profiler = profiler.lock().unwrap();
+            match debug.event() {
+                DebugEvent::Call => {
+                    profiler.enter(Self::profile_name(&debug));
+                }
+                // A tail call replaces the caller's frame, which gets no return event
+                DebugEvent::TailCall => {
+                    profiler.leave();
+                    profiler.enter(Self::profile_name(&debug));
+                }
+                DebugEvent::Ret => profiler.leave(),
+                _ => {}
+            }
+            Ok(())
+        });
+    }
+
+    /// Removes the profiling hooks; the collected report stays available.
+    pub fn disable_profiler(&self) {
+        self.lua.remove_hook();
+        self.profiler.lock().unwrap().stack.clear();
+    }
+
+    /// Function name, inclusive time and call count, most expensive first.
+    pub fn profile_report(&self) -> Vec<(String, Duration, u64)> {
+        self.profiler.lock().unwrap().report(|stats| stats.total)
+    }
+
+    /// Like `profile_report`, ranked by time spent in each function itself,
+    /// excluding the functions it called.
+    pub fn profile_report_exclusive(&self) -> Vec<(String, Duration, u64)> {
+        self.profiler.lock().unwrap().report(|stats| stats.exclusive)
+    }
+
+    fn profile_name(debug: &mlua::Debug) -> String {
+        if let Some(name) = debug.names().name {
+            return name.into_owned();
+        }
+        let source = debug.source();
+        format!(
+            "{}:{}",
+            source.short_src.as_deref().unwrap_or("?"),
+            source.line_defined.unwrap_or(0),
+        )
+    }
+
+    /// Exposes `kv` as a Lua global table: `kv.getInt(key)`, `kv.setInt(key, value)`,
+    /// the Float/String equivalents, `kv.getTable`/`kv.setTable` storing tables as
+    /// JSON strings, `kv.keys(prefix)`/`kv.scan(prefix)` listing string keys (without
+    /// the crate's own session, queue and HTTP cache entries), and
+    /// `kv.transaction(function(tx) ... end)`.
+    /// A transaction commits when the function returns and rolls back if it errors;
+    /// the plain `kv.set*` calls must not be used inside it since they wait for it to end.
+    pub fn bind_kv(&self, kv: Arc<KV>) -> Result<(), String> {
+        self.init_kv_api(kv).map_err(|e| e.to_string())
+    }
+
+    fn init_kv_api(&self, kv: Arc<KV>) -> mlua::Result<()> {
+        let table = self.lua.create_table()?;
+
+        let store = kv.clone();
+        table.set("getInt", self.lua.create_function(move |_, key: String| {
+            store.read_int(&key).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("setInt", self.lua.create_function(move |_, (key, value): (String, i64)| {
+            store.write_int(&key, value).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("getFloat", self.lua.create_function(move |_, key: String| {
+            store.read_float(&key).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("setFloat", self.lua.create_function(move |_, (key, value): (String, f64)| {
+            store.write_float(&key, value).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("getString", self.lua.create_function(move |_, key: String| {
+            store.read_string(&key).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("setString", self.lua.create_function(move |_, (key, value): (String, String)| {
+            store.write_string(&key, &value).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("getTable", self.lua.create_function(move |lua, key: String| {
+            let data = match store.read_string(&key) {
+                Ok(Some(data)) => data,
+                Ok(None) | Err(redb::Error::TableDoesNotExist(_)) => return Ok(mlua::Value::Nil),
+                Err(e) => return Err(mlua::Error::external(e)),
+            };
+            let json = serde_json::from_str(&data).map_err(mlua::Error::external)?;
+            json_to_lua(lua, &json)
+        })?)?;
+        let store = kv.clone();
+        table.set("setTable", self.lua.create_function(move |_, (key, value): (String, mlua::Table)| {
+            let json = lua_to_json(mlua::Value::Table(value), 0)?;
+            store.write_string(&key, &json.to_string()).map_err(mlua::Error::external)
+        })?)?;
+        let store = kv.clone();
+        table.set("keys", self.lua.create_function(move |_, prefix: Option<String>| {
+            let entries = script_entries(&store, prefix.as_deref())?;
+            Ok(entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>())
+        })?)?;
+        let store = kv.clone();
+        table.set("scan", self.lua.create_function(move |_, prefix: Option<String>| {
+            Ok(script_entries(&store, prefix.as_deref())?.into_iter().collect::<HashMap<_, _>>())
+        })?)?;
+
+        let store = kv.clone();
+        table.set("transaction", self.lua.create_function(move |_, func: Function| {
+            let tx = store.begin_transaction().map_err(mlua::Error::external)?;
+            let tx = Arc::new(Mutex::new(Some(tx)));
+            let result = func.call::<_, ()>(LuaKvTransaction(tx.clone()));
+            // Taking the transaction out invalidates any handle the script kept
+            let tx = tx.lock().unwrap().take();
+            result?;
+            match tx {
+                Some(tx) => tx.commit().map_err(mlua::Error::external),
+                None => Ok(()),
+            }
+        })?)?;
+
+        self.lua.globals().set("kv", table)
+    }
+
+    /// Exposes `http` as a Lua global table with `http.get(url[, headers])` and
+    /// `http.post(url[, body[, headers]])`, each returning a response with `:status()`,
+    /// `:header(name)`, `:headers()`, `:body()` (text decoded per the charset),
+    /// `:bytes()` (the raw body) and `:json()` methods,
+    /// plus `http.url(template, params)` for building URLs from `{name}` templates.
+    /// Requests block the calling script on `runtime`, so scripts must not run
+    /// inside one of that runtime's async tasks.
+    pub fn bind_http(&self, client: Arc<HttpClient>, runtime: Handle) -> Result<(), String> {
+        self.init_http_api(client, runtime).map_err(|e| e.to_string())
+    }
+
+    fn init_http_api(&self, client: Arc<HttpClient>, runtime: Handle) -> mlua::Result<()> {
+        let table = self.lua.create_table()?;
+
+        let (http, rt) = (client.clone(), runtime.clone());
+        table.set("get", self.lua.create_function(
+            move |_, (url, headers): (String, Option<HashMap<String, String>>)| {
+                let response = rt.block_on(http.get(&url, headers, None, None, None))
+                    .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+                Ok(LuaHttpResponse::from(response))
+            }
+        )?)?;
+
+        let (http, rt) = (client.clone(), runtime.clone());
+        table.set("post", self.lua.create_function(
+            move |_, (url, body, headers): (String, Option<String>, Option<HashMap<String, String>>)| {
+                let response = rt.block_on(http.post(&url, headers, body.as_deref(), None, None))
+                    .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+                Ok(LuaHttpResponse::from(response))
+            }
+        )?)?;
+
+        table.set("url", self.lua.create_function(
+            |_, (template, params): (String, Option<HashMap<String, String>>)| {
+                expand_url_template(&template, &params.unwrap_or_default())
+                    .map_err(mlua::Error::RuntimeError)
+            }
+        )?)?;
+
+        self.lua.globals().set("http", table)
+    }
+
+    pub fn load_file(&self, path: &str) -> Result<(), LuaError> {
         let path = Path::new(path);
-        self.lua.load(path).exec().map_err(|e| e.to_string())
+        let checkpoint = self.lock_checkpoint();
+        Ok(checkpoint.release_on_error(self.lua.load(path).exec())?)
+    }
+
+    pub fn load_string(&self, script: &str) -> Result<(), LuaError> {
+        let checkpoint = self.lock_checkpoint();
+        Ok(checkpoint.release_on_error(self.lua.load(script).exec())?)
     }
 
-    pub fn load_string(&self, script: &str) -> Result<(), String> {
-        self.lua.load(script).exec().map_err(|e| e.to_string())
+    pub fn call_function(&self, func_name: &str, arg: &str) -> Result<String, LuaError> {
+        let func: Function = self.lua.globals().get(func_name)?;
+        let checkpoint = self.lock_checkpoint();
+        Ok(checkpoint.release_on_error(func.call::<_, String>(arg))?)
     }
 
-    pub fn call_function(&self, func_name: &str, arg: &str) -> Result<String, String> {
-        let func: Function = self.lua.globals().get(func_name).map_err(|e| e.to_string())?;
-        func.call::<_, String>(arg).map_err(|e| e.to_string())
+    /// Calls the global function `name` in protected mode, returning all of its
+    /// results or the error message together with the Lua stack traceback.
+    pub fn try_call<'lua>(&'lua self, name: &str, args: Vec<mlua::Value<'lua>>) -> CallOutcome<'lua> {
+        let checkpoint = self.lock_checkpoint();
+        let result = self.lua.globals().get::<_, Function>(name)
+            .and_then(|func| func.call::<_, mlua::MultiValue>(mlua::MultiValue::from_vec(args)));
+        match checkpoint.release_on_error(result) {
+            Ok(values) => CallOutcome::Ok(values.into_vec()),
+            Err(e) => CallOutcome::from_error(e),
+        }
     }
 
+    /// Exposes `func` as the global `name`. Since bridges can move between threads,
+    /// as pooled ones do, `func` must be `Send`: functions capturing `Rc` or `RefCell`
+    /// state need `Arc` and `Mutex` instead.
     pub fn export_function<'a, F, R>(&self, name: &str, func: F) -> Result<(), String>
     where
-        F: Fn(&Lua, mlua::Value) -> mlua::Result<R> + 'static,
+        F: Fn(&Lua, mlua::Value) -> mlua::Result<R> + Send + 'static,
         R: for<'lua> mlua::IntoLuaMulti<'lua>,
     {
         let lua_func = self.lua.create_function(func).map_err(|e| e.to_string())?;
         self.lua.globals().set(name, lua_func).map_err(|e| e.to_string())
     }
 
-    // Generic version that works with any Rust function
+    /// Generic version that works with any Rust function, also required to be `Send`.
     pub fn export_rust_fn<F, A, R>(&self, name: &str, func: F) -> Result<(), String>
     where
-        F: Fn(A) -> R + 'static,
+        F: Fn(A) -> R + Send + 'static,
         A: for<'lua> mlua::FromLuaMulti<'lua>,
         R: for<'lua> mlua::IntoLuaMulti<'lua>,
     {
@@ -128,3 +801,663 @@ impl LuaBridge {
         self.lua.globals().set(name, lua_func).map_err(|e| e.to_string())
     }
 }
+
+// Guards against self-referencing tables
+const MAX_JSON_DEPTH: usize = 64;
+
+// Sequences (keys 1..n) become arrays and other tables objects with string keys;
+// an empty table is encoded as an empty object
+fn lua_to_json(value: mlua::Value, depth: usize) -> mlua::Result<serde_json::Value> {
+    if depth > MAX_JSON_DEPTH {
+        return Err(mlua::Error::RuntimeError("table nested too deeply for JSON".to_string()));
+    }
+    Ok(match value {
+        mlua::Value::Nil => serde_json::Value::Null,
+        mlua::Value::Boolean(b) => serde_json::Value::Bool(b),
+        mlua::Value::Integer(i) => serde_json::Value::from(i),
+        mlua::Value::Number(n) => serde_json::Number::from_f64(n)
+            .map_or(serde_json::Value::Null, serde_json::Value::Number),
+        mlua::Value::String(s) => serde_json::Value::String(s.to_str()?.to_string()),
+        mlua::Value::Table(table) => {
+            let len = table.raw_len();
+            let pairs = table.clone().pairs::<mlua::Value, mlua::Value>().count();
+            if len > 0 && pairs == len {
+                let items = table.sequence_values::<mlua::Value>()
+                    .map(|item| lua_to_json(item?, depth + 1))
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                serde_json::Value::Array(items)
+            } else {
+                let mut object = serde_json::Map::new();
+                for pair in table.pairs::<mlua::Value, mlua::Value>() {
+                    let (key, item) = pair?;
+                    let key = match key {
+                        mlua::Value::String(s) => s.to_str()?.to_string(),
+                        mlua::Value::Integer(i) => i.to_string(),
+                        other => return Err(mlua::Error::RuntimeError(
+                            format!("cannot encode a {} table key as JSON", other.type_name())
+                        )),
+                    };
+                    object.insert(key, lua_to_json(item, depth + 1)?);
+                }
+                serde_json::Value::Object(object)
+            }
+        }
+        other => return Err(mlua::Error::RuntimeError(
+            format!("cannot encode a {} as JSON", other.type_name())
+        )),
+    })
+}
+
+fn json_to_lua<'lua>(lua: &'lua Lua, json: &serde_json::Value) -> mlua::Result<mlua::Value<'lua>> {
+    Ok(match json {
+        serde_json::Value::Null => mlua::Value::Nil,
+        serde_json::Value::Bool(b) => mlua::Value::Boolean(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => mlua::Value::Integer(i),
+            None => mlua::Value::Number(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => mlua::Value::String(lua.create_string(s)?),
+        serde_json::Value::Array(items) => {
+            let table = lua.create_table_with_capacity(items.len(), 0)?;
+            for item in items {
+                table.raw_push(json_to_lua(lua, item)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+        serde_json::Value::Object(object) => {
+            let table = lua.create_table_with_capacity(0, object.len())?;
+            for (key, item) in object {
+                table.raw_set(key.as_str(), json_to_lua(lua, item)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+    })
+}
+
+// Matches what Lua's own `type()` reports
+fn lua_type_name(value: &mlua::Value) -> &'static str {
+    match value {
+        mlua::Value::Integer(_) | mlua::Value::Number(_) => "number",
+        mlua::Value::LightUserData(_) => "userdata",
+        other => other.type_name(),
+    }
+}
+
+// Returned by `http.get`/`http.post`. Only the raw body bytes are kept; they are
+// decoded to text on the first `body` or `json` call, and the text and parsed JSON
+// are kept for later calls.
+struct LuaHttpResponse {
+    status: reqwest::StatusCode,
+    headers: reqwest::header::HeaderMap,
+    bytes: Option<Vec<u8>>,
+    text: Option<String>,
+    json: Option<serde_json::Value>,
+}
+
+impl From<HttpResponse> for LuaHttpResponse {
+    fn from(response: HttpResponse) -> Self {
+        Self {
+            status: response.status,
+            headers: response.headers,
+            bytes: response.body_bytes,
+            text: None,
+            json: None,
+        }
+    }
+}
+
+impl LuaHttpResponse {
+    fn text(&mut self) -> Option<&str> {
+        if self.text.is_none() {
+            self.text = self.bytes.as_deref().map(|bytes| decode_text(&self.headers, bytes));
+        }
+        self.text.as_deref()
+    }
+}
+
+impl UserData for LuaHttpResponse {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("status", |_, this, ()| Ok(this.status.as_u16()));
+        methods.add_method("header", |_, this, name: String| {
+            Ok(this.headers.get(name.as_str()).and_then(|value| value.to_str().ok()).map(str::to_string))
+        });
+        methods.add_method("headers", |lua, this, ()| {
+            let headers = lua.create_table()?;
+            for (name, value) in this.headers.iter() {
+                headers.set(name.as_str(), value.to_str().unwrap_or(""))?;
+            }
+            Ok(headers)
+        });
+        methods.add_method_mut("body", |_, this, ()| Ok(this.text().map(str::to_string)));
+        methods.add_method("bytes", |lua, this, ()| {
+            // Lua strings hold arbitrary bytes, so binary bodies come through intact
+            this.bytes.as_deref().map(|body| lua.create_string(body)).transpose()
+        });
+        methods.add_method_mut("json", |lua, this, ()| {
+            if this.json.is_none() {
+                let json = serde_json::from_str(this.text().unwrap_or(""))
+                    .map_err(|e| mlua::Error::RuntimeError(format!("response is not valid JSON: {}", e)))?;
+                this.json = Some(json);
+            }
+            json_to_lua(lua, this.json.as_ref().unwrap())
+        });
+    }
+}
+
+/// A fixed set of pre-initialized bridges for running scripts concurrently.
+/// Every bridge owns an independent Lua state, so globals set by one script are
+/// never visible to scripts running on another bridge. Handing bridges to other
+/// threads is what needs mlua's `send` feature, and with it `Send` functions in
+/// `LuaBridge::export_function` and `export_rust_fn`.
+pub struct LuaBridgePool {
+    bridges: Mutex<Vec<LuaBridge>>,
+    available: Condvar,
+}
+
+/// A bridge borrowed from a `LuaBridgePool`, returned to it on drop.
+pub struct PooledLuaBridge<'a> {
+    pool: &'a LuaBridgePool,
+    bridge: Option<LuaBridge>,
+}
+
+impl LuaBridgePool {
+    /// Creates `size` bridges, running `init` on each to export the same APIs.
+    /// All bridges share one set of named locks.
+    pub fn new(size: usize, init: impl Fn(&LuaBridge)) -> Result<Self, String> {
+        let locks = Arc::new(NamedLocks::default());
+        let mut bridges = Vec::with_capacity(size);
+        for _ in 0..size {
+            let bridge = LuaBridge::new()?;
+            bridge.share_locks(locks.clone())?;
+            init(&bridge);
+            bridges.push(bridge);
+        }
+        Ok(Self {
+            bridges: Mutex::new(bridges),
+            available: Condvar::new(),
+        })
+    }
+
+    /// Blocks until a bridge is free.
+    pub fn acquire(&self) -> PooledLuaBridge<'_> {
+        let mut bridges = self.bridges.lock().unwrap();
+        loop {
+            if let Some(bridge) = bridges.pop() {
+                return PooledLuaBridge { pool: self, bridge: Some(bridge) };
+            }
+            bridges = self.available.wait(bridges).unwrap();
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<PooledLuaBridge<'_>> {
+        let bridge = self.bridges.lock().unwrap().pop()?;
+        Some(PooledLuaBridge { pool: self, bridge: Some(bridge) })
+    }
+}
+
+impl Deref for PooledLuaBridge<'_> {
+    type Target = LuaBridge;
+
+    fn deref(&self) -> &LuaBridge {
+        self.bridge.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledLuaBridge<'_> {
+    fn drop(&mut self) {
+        if let Some(bridge) = self.bridge.take() {
+            self.pool.bridges.lock().unwrap().push(bridge);
+            self.pool.available.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response, serve, temp_kv};
+
+    fn bridge_with_kv() -> (LuaBridge, Arc<KV>, crate::test_util::TempPath) {
+        let (kv, path) = temp_kv();
+        let kv = Arc::new(kv);
+        let bridge = LuaBridge::new().unwrap();
+        bridge.bind_kv(kv.clone()).unwrap();
+        (bridge, kv, path)
+    }
+
+    #[test]
+    fn transaction_commits_a_transfer() {
+        let (bridge, kv, _path) = bridge_with_kv();
+        kv.write_int("a", 100).unwrap();
+        kv.write_int("b", 0).unwrap();
+        bridge.load_string(r#"
+            kv.transaction(function(tx)
+                tx:setInt("a", tx:getInt("a") - 30)
+                tx:setInt("b", tx:getInt("b") + 30)
+            end)
+        "#).unwrap();
+        assert_eq!(kv.read_int("a").unwrap(), Some(70));
+        assert_eq!(kv.read_int("b").unwrap(), Some(30));
+    }
+
+    #[test]
+    fn transaction_rolls_back_when_the_script_errors() {
+        let (bridge, kv, _path) = bridge_with_kv();
+        kv.write_int("a", 100).unwrap();
+        kv.write_int("b", 0).unwrap();
+        let err = bridge.load_string(r#"
+            kv.transaction(function(tx)
+                tx:setInt("a", tx:getInt("a") - 30)
+                error("insufficient funds")
+            end)
+        "#).unwrap_err();
+        assert!(err.message.contains("insufficient funds"));
+        assert_eq!(kv.read_int("a").unwrap(), Some(100));
+        assert_eq!(kv.read_int("b").unwrap(), Some(0));
+    }
+
+    #[test]
+    fn transaction_handle_is_closed_afterwards() {
+        let (bridge, _kv, _path) = bridge_with_kv();
+        let err = bridge.load_string(r#"
+            local saved
+            kv.transaction(function(tx) saved = tx end)
+            saved:setInt("a", 1)
+        "#).unwrap_err();
+        assert!(err.message.contains("KV transaction is closed"));
+    }
+
+    #[test]
+    fn pooled_bridges_keep_their_own_globals() {
+        let pool = LuaBridgePool::new(2, |bridge| {
+            bridge.load_string("function greet(who) return name .. ' greets ' .. who end").unwrap();
+        }).unwrap();
+        let ready = std::sync::Barrier::new(2);
+        let results: Vec<String> = std::thread::scope(|scope| {
+            let workers: Vec<_> = ["a", "b"].into_iter().map(|name| {
+                let (pool, ready) = (&pool, &ready);
+                scope.spawn(move || {
+                    let bridge = pool.acquire();
+                    bridge.load_string(&format!("name = '{}'", name)).unwrap();
+                    // Both globals are set before either bridge reads its own
+                    ready.wait();
+                    bridge.call_function("greet", "you").unwrap()
+                })
+            }).collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        assert_eq!(results, ["a greets you", "b greets you"]);
+    }
+
+    #[test]
+    fn try_call_reports_results_and_errors() {
+        let bridge = LuaBridge::new().unwrap();
+        bridge.load_string(r#"
+            function divmod(a, b) return a // b, a % b end
+            function fail() error("no such save") end
+        "#).unwrap();
+
+        let args = vec![mlua::Value::Integer(7), mlua::Value::Integer(3)];
+        match bridge.try_call("divmod", args) {
+            CallOutcome::Ok(values) => {
+                let values: Vec<_> = values.iter().map(|v| v.as_i64()).collect();
+                assert_eq!(values, [Some(2), Some(1)]);
+            }
+            CallOutcome::Err { message, .. } => panic!("divmod failed: {}", message),
+        }
+
+        match bridge.try_call("fail", Vec::new()) {
+            CallOutcome::Ok(_) => panic!("fail succeeded"),
+            CallOutcome::Err { message, traceback } => {
+                assert!(message.contains("no such save"), "{}", message);
+                assert!(traceback.starts_with("stack traceback:"), "{}", traceback);
+                assert!(traceback.contains("fail"), "{}", traceback);
+            }
+        }
+    }
+
+    #[test]
+    fn expired_timers_fire_by_priority() {
+        let bridge = LuaBridge::new().unwrap();
+        bridge.load_string(r#"
+            fired = {}
+            function low() table.insert(fired, "low") end
+            function normal() table.insert(fired, "normal") end
+            function high() table.insert(fired, "high") end
+            addTimer({0, "low", -5})
+            addTimer({0, "normal"})
+            addTimer({0, "high", 5})
+            pollTimers()
+            order = table.concat(fired, ",")
+        "#).unwrap();
+        let order: String = bridge.lua.globals().get("order").unwrap();
+        assert_eq!(order, "high,normal,low");
+    }
+
+    #[test]
+    fn keys_and_scan_list_script_keys_only() {
+        let (bridge, kv, _path) = bridge_with_kv();
+        kv.write_string("__session:abc", "{}").unwrap();
+        kv.write_string("__queue:jobs:0", "{}").unwrap();
+        bridge.load_string(r#"
+            kv.setString("save:1", "forest")
+            kv.setString("save:2", "cave")
+            kv.setString("save:3", "castle")
+            kv.setString("settings", "{}")
+            saves = kv.keys("save:")
+            everything = kv.keys()
+            scanned = kv.scan("save:")
+        "#).unwrap();
+
+        let globals = bridge.lua.globals();
+        let saves: Vec<String> = globals.get("saves").unwrap();
+        assert_eq!(saves, ["save:1", "save:2", "save:3"]);
+        let everything: Vec<String> = globals.get("everything").unwrap();
+        assert_eq!(everything, ["save:1", "save:2", "save:3", "settings"]);
+        let scanned: HashMap<String, String> = globals.get("scanned").unwrap();
+        assert_eq!(scanned.len(), 3);
+        assert_eq!(scanned["save:2"], "cave");
+    }
+
+    #[test]
+    fn http_url_escapes_params() {
+        let bridge = LuaBridge::new().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        bridge.bind_http(Arc::new(HttpClient::new(None).unwrap()), runtime.handle().clone()).unwrap();
+        bridge.load_string(r#"
+            url = http.url("https://api.example.com/{id}/items", { id = "a b/c" })
+            ok, err = pcall(http.url, "https://api.example.com/{id}/{page}", { id = 5 })
+            err = tostring(err)
+        "#).unwrap();
+
+        let globals = bridge.lua.globals();
+        let url: String = globals.get("url").unwrap();
+        assert_eq!(url, "https://api.example.com/a%20b%2Fc/items");
+        assert!(!globals.get::<_, bool>("ok").unwrap());
+        let err: String = globals.get("err").unwrap();
+        assert!(err.contains("page"), "{}", err);
+    }
+
+    #[test]
+    fn failing_timer_callbacks_do_not_stop_the_poll() {
+        let bridge = LuaBridge::new().unwrap();
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let seen = errors.clone();
+        bridge.set_timer_error_handler(move |name, e| {
+            seen.lock().unwrap().push((name.to_string(), e.kind, e.message.clone()));
+        });
+        bridge.load_string(r#"
+            fired = {}
+            function first() table.insert(fired, "first") end
+            function broken() error("callback failed") end
+            function replaced() end
+            function last() table.insert(fired, "last") end
+            function tick() table.insert(fired, "tick") end
+            addTimer({0, "first", 3})
+            addTimer({0, "broken", 2})
+            addTimer({0, "replaced", 1})
+            addTimer({0, "last", 0})
+            nextTick("tick")
+            replaced = 42
+            pollTimers()
+            order = table.concat(fired, ",")
+        "#).unwrap();
+
+        let order: String = bridge.lua.globals().get("order").unwrap();
+        assert_eq!(order, "tick,first,last");
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].0.as_str(), errors[0].1), ("broken", LuaErrorKind::Runtime));
+        assert!(errors[0].2.contains("callback failed"), "{}", errors[0].2);
+        assert_eq!((errors[1].0.as_str(), errors[1].1), ("replaced", LuaErrorKind::Other));
+        assert!(errors[1].2.contains("not a function (got integer)"), "{}", errors[1].2);
+    }
+
+    #[test]
+    fn without_a_handler_the_poll_raises_after_running_every_callback() {
+        let bridge = LuaBridge::new().unwrap();
+        let err = bridge.load_string(r#"
+            fired = {}
+            function broken() error("callback failed") end
+            function last() table.insert(fired, "last") end
+            addTimer({0, "broken", 1})
+            addTimer({0, "last", 0})
+            pollTimers()
+        "#).unwrap_err();
+        assert!(err.message.contains("callback failed"), "{}", err);
+        let fired: Vec<String> = bridge.lua.globals().get("fired").unwrap();
+        assert_eq!(fired, ["last"]);
+    }
+
+    #[test]
+    fn assert_type_names_both_types() {
+        let bridge = LuaBridge::new().unwrap();
+        let err = bridge.load_string(r#"assertType("x", "number")"#).unwrap_err();
+        assert!(err.message.contains("[E_TYPE] expected number, got string"), "{}", err);
+
+        bridge.load_string(r#"
+            n = assertType(5, "number")
+            ok, range_err = pcall(assertRange, 11, 1, 10)
+            ok, field_err = pcall(require_field, {}, "name")
+            range_err, field_err = tostring(range_err), tostring(field_err)
+        "#).unwrap();
+        let globals = bridge.lua.globals();
+        assert_eq!(globals.get::<_, i64>("n").unwrap(), 5);
+        assert!(globals.get::<_, String>("range_err").unwrap().contains("[E_RANGE]"));
+        assert!(globals.get::<_, String>("field_err").unwrap().contains("[E_FIELD] missing required field 'name'"));
+    }
+
+    #[test]
+    fn timer_handles_compare_by_timer_and_go_inactive_once_fired() {
+        let bridge = LuaBridge::new().unwrap();
+        bridge.load_string(r#"
+            function cb() end
+            timer = addTimer({0, "cb"})
+            later = addTimer({60, "cb"})
+        "#).unwrap();
+        let globals = bridge.lua.globals();
+        let timer: mlua::AnyUserData = globals.get("timer").unwrap();
+        let copy = timer.borrow::<TimerHandle>().unwrap().clone();
+        globals.set("copy", bridge.lua.create_userdata(copy).unwrap()).unwrap();
+
+        bridge.load_string(r#"
+            same, different = timer == copy, timer == later
+            active_before = timer:isActive()
+            pollTimers()
+            active_after, remaining_after = timer:isActive(), timer:remaining()
+            later_remaining = later:remaining()
+        "#).unwrap();
+        assert!(globals.get::<_, bool>("same").unwrap());
+        assert!(!globals.get::<_, bool>("different").unwrap());
+        assert!(globals.get::<_, bool>("active_before").unwrap());
+        assert!(!globals.get::<_, bool>("active_after").unwrap());
+        assert!(globals.get::<_, Option<f64>>("remaining_after").unwrap().is_none());
+        let later_remaining: f64 = globals.get("later_remaining").unwrap();
+        assert!(later_remaining > 59.0 && later_remaining <= 60.0, "{}", later_remaining);
+    }
+
+    #[test]
+    fn next_tick_runs_in_order_on_the_next_poll() {
+        let bridge = LuaBridge::new().unwrap();
+        bridge.load_string(r#"
+            fired = {}
+            function first() table.insert(fired, "first"); nextTick("requeued") end
+            function second() table.insert(fired, "second") end
+            function requeued() table.insert(fired, "requeued") end
+            nextTick("first")
+            nextTick("second")
+            before = #fired
+            pollTimers()
+            after_first = table.concat(fired, ",")
+        "#).unwrap();
+        let globals = bridge.lua.globals();
+        assert_eq!(globals.get::<_, i64>("before").unwrap(), 0);
+        // A tick queued by a tick waits for the following poll
+        assert_eq!(globals.get::<_, String>("after_first").unwrap(), "first,second");
+        assert_eq!(bridge.timers.lock().unwrap().deferred.len(), 1);
+
+        bridge.load_string("pollTimers()").unwrap();
+        let fired: Vec<String> = globals.get("fired").unwrap();
+        assert_eq!(fired, ["first", "second", "requeued"]);
+        assert!(bridge.timers.lock().unwrap().deferred.is_empty());
+    }
+
+    #[test]
+    fn contending_coroutines_take_turns() {
+        let bridge = LuaBridge::new().unwrap();
+        bridge.load_string(r#"
+            inside, log = 0, {}
+            local function worker(name)
+                return coroutine.create(function()
+                    lock("resource")
+                    inside = inside + 1
+                    table.insert(log, name .. ">" .. inside)
+                    coroutine.yield()
+                    inside = inside - 1
+                    table.insert(log, name .. "<")
+                    unlock("resource")
+                end)
+            end
+            local workers = { worker("a"), worker("b") }
+            repeat
+                local running = false
+                for _, co in ipairs(workers) do
+                    if coroutine.status(co) ~= "dead" then
+                        assert(coroutine.resume(co))
+                        running = true
+                    end
+                end
+            until not running
+            order = table.concat(log, ",")
+        "#).unwrap();
+        let order: String = bridge.lua.globals().get("order").unwrap();
+        assert_eq!(order, "a>1,a<,b>1,b<");
+    }
+
+    #[test]
+    fn a_failed_call_releases_the_locks_it_took() {
+        let pool = LuaBridgePool::new(2, |_| {}).unwrap();
+        let (first, second) = (pool.acquire(), pool.acquire());
+        first.load_string(r#"lock("kept")"#).unwrap();
+
+        let err = first.load_string(r#"lock("kept"); lock("taken"); error("boom")"#).unwrap_err();
+        assert!(err.message.contains("boom"));
+        let probe = r#"free = {}; for _, name in ipairs({"kept", "taken"}) do
+            free[name] = tryLock(name); if free[name] then unlock(name) end end"#;
+        second.load_string(probe).unwrap();
+        let free: HashMap<String, bool> = second.lua.globals().get("free").unwrap();
+        assert_eq!(free, HashMap::from([("kept".to_string(), false), ("taken".to_string(), true)]));
+
+        // The hold from the first call is back to one level, so one unlock frees it
+        first.load_string(r#"unlock("kept")"#).unwrap();
+        second.load_string(probe).unwrap();
+        let free: HashMap<String, bool> = second.lua.globals().get("free").unwrap();
+        assert!(free["kept"]);
+    }
+
+    #[test]
+    fn tables_round_trip_through_kv_into_a_fresh_bridge() {
+        let (writer, kv, _path) = bridge_with_kv();
+        writer.load_string(r#"
+            kv.setTable("save", {
+                name = "forest",
+                level = 3,
+                ratio = 0.5,
+                done = false,
+                items = {"sword", "rope"},
+                pos = {x = 1, y = -2, tags = {"spawn"}},
+            })
+        "#).unwrap();
+
+        let reader = LuaBridge::new().unwrap();
+        reader.bind_kv(kv.clone()).unwrap();
+        reader.load_string(r#"
+            local function equal(a, b)
+                if type(a) ~= type(b) then return false end
+                if type(a) ~= "table" then return a == b end
+                for k, v in pairs(a) do
+                    if not equal(v, b[k]) then return false end
+                end
+                for k in pairs(b) do
+                    if a[k] == nil then return false end
+                end
+                return true
+            end
+            same = equal(kv.getTable("save"), {
+                name = "forest",
+                level = 3,
+                ratio = 0.5,
+                done = false,
+                items = {"sword", "rope"},
+                pos = {x = 1, y = -2, tags = {"spawn"}},
+            })
+            missing = kv.getTable("nothing") == nil
+        "#).unwrap();
+
+        let globals = reader.lua.globals();
+        assert!(globals.get::<_, bool>("same").unwrap());
+        assert!(globals.get::<_, bool>("missing").unwrap());
+    }
+
+    #[test]
+    fn profiler_ranks_the_heavier_function_first() {
+        let bridge = LuaBridge::new().unwrap();
+        bridge.load_string(r#"
+            function heavy()
+                local sum = 0
+                for i = 1, 300000 do sum = sum + i % 7 end
+                return sum
+            end
+            function light()
+                return 1
+            end
+        "#).unwrap();
+
+        bridge.enable_profiler();
+        bridge.load_string(r#"
+            for _ = 1, 5 do light() end
+            for _ = 1, 2 do heavy() end
+        "#).unwrap();
+        bridge.disable_profiler();
+        // Calls after disabling are not counted
+        bridge.load_string("heavy()").unwrap();
+
+        let report = bridge.profile_report();
+        let position = |name: &str| report.iter().position(|(function, _, _)| function == name).unwrap();
+        let (heavy, light) = (position("heavy"), position("light"));
+        assert!(heavy < light, "{:?}", report);
+        assert_eq!(report[heavy].2, 2);
+        assert_eq!(report[light].2, 5);
+        assert!(report[heavy].1 > report[light].1);
+    }
+
+    #[test]
+    fn response_body_is_decoded_only_when_asked_for() {
+        let url = serve(|request| match request.target.as_str() {
+            "/latin1" => response(200, &[("content-type", "text/plain; charset=iso-8859-1")], b"caf\xe9"),
+            _ => response(200, &[("content-type", "application/json")], br#"{"items":[1,2],"ok":true}"#),
+        });
+        let bridge = LuaBridge::new().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        bridge.bind_http(Arc::new(HttpClient::new(None).unwrap()), runtime.handle().clone()).unwrap();
+        bridge.lua.globals().set("base", url).unwrap();
+
+        bridge.load_string("rsp = http.get(base) status = rsp:status()").unwrap();
+        let globals = bridge.lua.globals();
+        assert_eq!(globals.get::<_, u16>("status").unwrap(), 200);
+        let rsp: mlua::AnyUserData = globals.get("rsp").unwrap();
+        assert!(rsp.borrow::<LuaHttpResponse>().unwrap().text.is_none());
+
+        bridge.load_string(r#"
+            local json = rsp:json()
+            count, ok = #json.items, json.ok
+            latin1 = http.get(base .. "/latin1")
+            text, raw = latin1:body(), latin1:bytes()
+        "#).unwrap();
+        assert!(rsp.borrow::<LuaHttpResponse>().unwrap().text.is_some());
+        assert_eq!(globals.get::<_, i64>("count").unwrap(), 2);
+        assert!(globals.get::<_, bool>("ok").unwrap());
+        assert_eq!(globals.get::<_, String>("text").unwrap(), "caf\u{e9}");
+        assert_eq!(globals.get::<_, mlua::String>("raw").unwrap().as_bytes(), b"caf\xe9");
+    }
+}
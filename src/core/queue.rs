@@ -0,0 +1,192 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::error::Error;
+use serde_json::{json, Value};
+use crate::core::kv::KV;
+use crate::core::net::{HttpClient, HttpError, Method};
+
+// Entries live in the string table under "__queue:<name>:<seq>", with <seq>
+// zero-padded so key order is enqueue order; the next seq is an int entry.
+// `:` and `%` in <name> are percent-escaped, so queue "a" never sees "a:b"'s entries
+pub(crate) const KEY_PREFIX: &str = "__queue:";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueuedRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+/// How far `RequestQueue::flush` got.
+#[derive(Debug)]
+pub struct FlushReport {
+    /// Requests sent and removed from the queue.
+    pub delivered: usize,
+    /// Why the flush stopped before emptying the queue: the failed request's error,
+    /// `HttpError::Status` for a non-2xx answer, or a KV error. `None` when it emptied.
+    pub error: Option<Box<dyn Error>>,
+}
+
+/// A durable FIFO of requests stored in a KV store, for replaying once the
+/// network is reachable again. Flushes of one queue must not run concurrently,
+/// or a request may be sent twice.
+pub struct RequestQueue<'a> {
+    kv: &'a KV,
+    name: String,
+}
+
+impl QueuedRequest {
+    fn to_json(&self) -> String {
+        json!({
+            "method": self.method.as_str(),
+            "url": self.url,
+            "headers": self.headers,
+            "body": self.body,
+        }).to_string()
+    }
+
+    fn from_json(data: &str) -> Result<Self, Box<dyn Error>> {
+        let value: Value = serde_json::from_str(data)?;
+        let method = value["method"].as_str().ok_or("queued request has no method")?;
+        let url = value["url"].as_str().ok_or("queued request has no url")?;
+        Ok(Self {
+            method: Method::from_bytes(method.as_bytes())?,
+            url: url.to_string(),
+            headers: serde_json::from_value(value["headers"].clone())?,
+            body: value["body"].as_str().map(str::to_string),
+        })
+    }
+}
+
+impl<'a> RequestQueue<'a> {
+    pub fn new(kv: &'a KV, name: &str) -> Self {
+        Self { kv, name: name.to_string() }
+    }
+
+    fn counter_key(&self) -> String {
+        format!("{}{}", KEY_PREFIX, self.name.replace('%', "%25").replace(':', "%3A"))
+    }
+
+    fn entry_prefix(&self) -> String {
+        format!("{}:", self.counter_key())
+    }
+
+    pub fn enqueue<K, V>(
+        &self,
+        method: Method,
+        url: &str,
+        headers: Option<HashMap<K, V>>,
+        body: Option<&str>,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        K: Borrow<str>,
+        V: Borrow<str>,
+    {
+        let request = QueuedRequest {
+            method,
+            url: url.to_string(),
+            headers: headers.into_iter()
+                .flatten()
+                .map(|(k, v)| (k.borrow().to_string(), v.borrow().to_string()))
+                .collect(),
+            body: body.map(str::to_string),
+        };
+
+        // Allocating the sequence number and storing the entry share one transaction
+        let counter_key = self.counter_key();
+        let mut tx = self.kv.begin_transaction()?;
+        let seq = tx.read_int(&counter_key)?.unwrap_or(0);
+        tx.write_int(&counter_key, seq + 1)?;
+        tx.write_string(&format!("{}{:020}", self.entry_prefix(), seq), &request.to_json())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Queued requests, oldest first.
+    pub fn pending(&self) -> Result<Vec<QueuedRequest>, Box<dyn Error>> {
+        self.kv.scan_prefix_string(&self.entry_prefix())?
+            .iter()
+            .map(|(_, data)| QueuedRequest::from_json(data))
+            .collect()
+    }
+
+    pub fn len(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.kv.scan_prefix_string(&self.entry_prefix())?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Sends queued requests oldest first, removing each once it gets a 2xx
+    /// response. Stops at the first request that fails or gets any other status,
+    /// leaving it and everything after it queued, and reports how many were
+    /// delivered before that along with the reason it stopped.
+    pub async fn flush(&self, client: &HttpClient) -> FlushReport {
+        let mut report = FlushReport { delivered: 0, error: None };
+        if let Err(e) = self.flush_into(client, &mut report).await {
+            report.error = Some(e);
+        }
+        report
+    }
+
+    async fn flush_into(&self, client: &HttpClient, report: &mut FlushReport) -> Result<(), Box<dyn Error>> {
+        for (key, data) in self.kv.scan_prefix_string(&self.entry_prefix())? {
+            let request = QueuedRequest::from_json(&data)?;
+            let mut builder = client.request(request.method, &request.url);
+            for (name, value) in request.headers {
+                builder = builder.header(name, value);
+            }
+            if let Some(body) = request.body {
+                builder = builder.body(body);
+            }
+            let response = builder.send().await?;
+            if !response.status.is_success() {
+                return Err(Box::new(HttpError::Status(response.status)));
+            }
+            self.kv.delete_string(&key)?;
+            report.delivered += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response, serve, temp_kv};
+
+    #[tokio::test]
+    async fn flush_stops_at_the_first_failure() {
+        let url = serve(|request| match request.target.as_str() {
+            "/3" => response(500, &[], b"down"),
+            _ => response(200, &[], b"ok"),
+        });
+        let (kv, _path) = temp_kv();
+        let queue = RequestQueue::new(&kv, "outbox");
+        for n in 1..=3 {
+            queue.enqueue::<&str, &str>(Method::POST, &format!("{}/{}", url, n), None, Some("body")).unwrap();
+        }
+
+        let report = queue.flush(&HttpClient::new(None).unwrap()).await;
+        assert_eq!(report.delivered, 2);
+        let error = report.error.expect("the third request should fail");
+        assert!(matches!(error.downcast_ref::<HttpError>(), Some(HttpError::Status(status)) if *status == 500));
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].url, format!("{}/3", url));
+    }
+
+    #[test]
+    fn queue_names_do_not_overlap() {
+        let (kv, _path) = temp_kv();
+        let a = RequestQueue::new(&kv, "a");
+        let ab = RequestQueue::new(&kv, "a:b");
+        a.enqueue::<&str, &str>(Method::GET, "http://example.com/a", None, None).unwrap();
+        ab.enqueue::<&str, &str>(Method::GET, "http://example.com/ab", None, None).unwrap();
+        ab.enqueue::<&str, &str>(Method::GET, "http://example.com/ab", None, None).unwrap();
+        assert_eq!(a.len().unwrap(), 1);
+        assert_eq!(ab.len().unwrap(), 2);
+    }
+}
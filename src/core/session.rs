@@ -0,0 +1,85 @@
+use std::time::Duration;
+use redb::Error;
+use crate::core::crypto::{bytes2hex, random_bytes};
+use crate::core::kv::KV;
+
+// Sessions live in the string table under "__session:<id>", expiring through the KV TTLs
+pub(crate) const KEY_PREFIX: &str = "__session:";
+
+// 256 bits, hex encoded
+const SESSION_ID_BYTES: usize = 32;
+
+/// Sessions stored in a KV store with a sliding expiry: every `get` restarts
+/// the `ttl` countdown, and a session not read within `ttl` is gone.
+pub struct SessionStore<'a> {
+    kv: &'a KV,
+    ttl: Duration,
+}
+
+impl<'a> SessionStore<'a> {
+    pub fn new(kv: &'a KV, ttl: Duration) -> Self {
+        Self { kv, ttl }
+    }
+
+    fn key(session_id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, session_id)
+    }
+
+    /// Stores `data` under a new random session ID and returns the ID.
+    pub fn create(&self, data: &str) -> Result<String, Error> {
+        let session_id = bytes2hex(&random_bytes(SESSION_ID_BYTES));
+        let key = Self::key(&session_id);
+        // In one transaction, so no session is ever stored without its expiry
+        self.kv.transaction(|tx| {
+            tx.write_string(&key, data)?;
+            tx.expire(&key, self.ttl)
+        })?;
+        Ok(session_id)
+    }
+
+    /// The session's data, or `None` once it expired or was destroyed.
+    /// Extends the session's life by another `ttl`.
+    pub fn get(&self, session_id: &str) -> Result<Option<String>, Error> {
+        let key = Self::key(session_id);
+        let data = self.kv.read_string(&key)?;
+        if data.is_some() {
+            self.kv.expire(&key, self.ttl)?;
+        }
+        Ok(data)
+    }
+
+    /// Returns whether the session existed.
+    pub fn destroy(&self, session_id: &str) -> Result<bool, Error> {
+        // An expired session counts as gone even while it is still stored
+        self.kv.delete_string(&Self::key(session_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_kv;
+
+    #[test]
+    fn reading_a_session_extends_its_life() {
+        // Reads come well within the TTL and the final check well after it, so a
+        // slow test machine does not flip the outcome
+        let (kv, _path) = temp_kv();
+        let sessions = SessionStore::new(&kv, Duration::from_millis(1000));
+        let touched = sessions.create("alice").unwrap();
+        let untouched = sessions.create("bob").unwrap();
+        assert_ne!(touched, untouched);
+        assert_eq!(touched.len(), SESSION_ID_BYTES * 2);
+
+        for _ in 0..4 {
+            std::thread::sleep(Duration::from_millis(500));
+            assert_eq!(sessions.get(&touched).unwrap().as_deref(), Some("alice"));
+        }
+        // 2s have passed, twice the window for the session nobody read
+        assert_eq!(sessions.get(&untouched).unwrap(), None);
+        assert!(!sessions.destroy(&untouched).unwrap());
+
+        assert!(sessions.destroy(&touched).unwrap());
+        assert_eq!(sessions.get(&touched).unwrap(), None);
+    }
+}
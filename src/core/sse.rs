@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+/// Incremental `text/event-stream` parser for `HttpClient::sse`, fed the body as it
+/// arrives. Lines may end in CRLF, LF or CR, and may be split across chunks.
+#[derive(Default)]
+pub(crate) struct SseParser {
+    // Bytes of a line whose terminator has not arrived yet
+    line: Vec<u8>,
+    // The last chunk ended in CR, so a LF opening the next one completes a CRLF
+    after_cr: bool,
+    // Whether this connection's first line was seen, which may start with a BOM
+    started: bool,
+    event: String,
+    data: String,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl SseParser {
+    /// The ID to send as `Last-Event-ID` when reconnecting.
+    pub(crate) fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// The reconnection delay the server asked for with a `retry:` field.
+    pub(crate) fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Starts over on a new connection, dropping any partly received event. The
+    /// last event ID and retry delay carry over.
+    pub(crate) fn reconnected(&mut self) {
+        self.line.clear();
+        self.after_cr = false;
+        self.started = false;
+        self.event.clear();
+        self.data.clear();
+    }
+
+    /// Parses the next chunk of the body, passing each completed event to
+    /// `on_event` as `(event, data, id)`. Returns `false` as soon as `on_event` does.
+    pub(crate) fn feed(&mut self, chunk: &[u8], mut on_event: impl FnMut(&str, &str, Option<&str>) -> bool) -> bool {
+        let mut rest = chunk;
+        if std::mem::take(&mut self.after_cr) && rest.first() == Some(&b'\n') {
+            rest = &rest[1..];
+        }
+        while let Some(end) = rest.iter().position(|&b| b == b'\r' || b == b'\n') {
+            self.line.extend_from_slice(&rest[..end]);
+            let line = std::mem::take(&mut self.line);
+            let crlf = rest[end] == b'\r' && rest.get(end + 1) == Some(&b'\n');
+            self.after_cr = rest[end] == b'\r' && end + 1 == rest.len();
+            rest = &rest[end + if crlf { 2 } else { 1 }..];
+            if !self.process_line(&String::from_utf8_lossy(&line), &mut on_event) {
+                return false;
+            }
+        }
+        self.line.extend_from_slice(rest);
+        true
+    }
+
+    fn process_line(&mut self, line: &str, on_event: &mut impl FnMut(&str, &str, Option<&str>) -> bool) -> bool {
+        let line = match std::mem::replace(&mut self.started, true) {
+            false => line.strip_prefix('\u{feff}').unwrap_or(line),
+            true => line,
+        };
+
+        // A blank line ends the event; one without data is dropped
+        if line.is_empty() {
+            let event = std::mem::take(&mut self.event);
+            let data = std::mem::take(&mut self.data);
+            if data.is_empty() {
+                return true;
+            }
+            let event = if event.is_empty() { "message" } else { &event };
+            return on_event(event, data.strip_suffix('\n').unwrap_or(&data), self.last_event_id.as_deref());
+        }
+        // Comments, often sent as keep-alives
+        if line.starts_with(':') {
+            return true;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            // An empty ID resets it; one holding NUL is ignored
+            "id" if !value.contains('\0') => self.last_event_id = (!value.is_empty()).then(|| value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok().map(Duration::from_millis);
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A BOM, comments, CRLF, LF and CR line endings, and an ID reset
+    const BODY: &[u8] = b"\xef\xbb\xbf: keep-alive\r\n\
+        retry: 2500\r\n\
+        event: update\r\n\
+        id: 7\r\n\
+        data: first line\r\n\
+        data:second line\r\n\
+        \r\n\
+        : another comment\n\
+        data: no type\n\
+        \n\
+        id\n\
+        event: dropped without data\n\
+        \n\
+        data: after the reset\r\r";
+
+    fn events(parser: &mut SseParser, chunks: &[&[u8]]) -> Vec<String> {
+        let mut events = Vec::new();
+        for chunk in chunks {
+            parser.feed(chunk, |event, data, id| {
+                events.push(format!("{} {:?} {:?}", event, data, id));
+                true
+            });
+        }
+        events
+    }
+
+    #[test]
+    fn a_canned_body_parses_the_same_whole_or_byte_by_byte() {
+        let expected = [
+            r#"update "first line\nsecond line" Some("7")"#,
+            r#"message "no type" Some("7")"#,
+            r#"message "after the reset" None"#,
+        ];
+        let mut whole = SseParser::default();
+        assert_eq!(events(&mut whole, &[BODY]), expected);
+
+        let mut bytewise = SseParser::default();
+        let bytes = BODY.chunks(1).collect::<Vec<_>>();
+        assert_eq!(events(&mut bytewise, &bytes), expected);
+
+        for parser in [whole, bytewise] {
+            assert_eq!(parser.retry(), Some(Duration::from_millis(2500)));
+            assert_eq!(parser.last_event_id(), None);
+        }
+    }
+
+    #[test]
+    fn stopping_and_reconnecting_drop_what_is_left_but_keep_the_id() {
+        let mut parser = SseParser::default();
+        let mut seen = 0;
+        assert!(!parser.feed(b"id: 1\ndata: a\n\ndata: b\n\n", |_, _, _| {
+            seen += 1;
+            false
+        }));
+        assert_eq!(seen, 1);
+
+        assert!(parser.feed(b"data: partial", |_, _, _| unreachable!()));
+        parser.reconnected();
+        assert_eq!(events(&mut parser, &[b"\xef\xbb\xbfdata: c\n\n"]), [r#"message "c" Some("1")"#]);
+    }
+}
@@ -0,0 +1,470 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use crate::core::net::RetryPolicy;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// How long `close` waits for the server to answer the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Messages queued while reconnecting, by default
+const DEFAULT_OFFLINE_BUFFER: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The server closed the connection, with its close code and reason if it sent them.
+    Close(Option<(u16, String)>),
+}
+
+impl WsMessage {
+    fn into_message(self) -> Message {
+        match self {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Binary(data) => Message::Binary(data),
+            WsMessage::Close(frame) => Message::Close(frame.map(|(code, reason)| CloseFrame {
+                code: CloseCode::from(code),
+                reason: reason.into(),
+            })),
+        }
+    }
+}
+
+/// What `send_text` and `send_binary` do while an auto-reconnecting client is
+/// disconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsOfflineSends {
+    /// Queue up to this many messages and send them once reconnected; sending
+    /// more fails.
+    Buffer(usize),
+    /// Discard them, reporting success.
+    Drop,
+}
+
+/// How `WsClient` reconnects after the connection drops without a closing handshake.
+#[derive(Clone, Debug)]
+pub struct WsReconnect {
+    /// Connection attempts and the backoff before each one. The status and method
+    /// lists only apply to HTTP requests and are ignored.
+    pub retry: RetryPolicy,
+    pub offline: WsOfflineSends,
+}
+
+impl Default for WsReconnect {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy {
+                max_attempts: 10,
+                base_delay: Duration::from_millis(500),
+                max_delay: Duration::from_secs(30),
+                ..RetryPolicy::default()
+            },
+            offline: WsOfflineSends::Buffer(DEFAULT_OFFLINE_BUFFER),
+        }
+    }
+}
+
+type ReconnectHook = Arc<dyn Fn() -> Vec<WsMessage> + Send + Sync>;
+
+/// A WebSocket connection (`ws://` or `wss://`). Sending and receiving use separate
+/// halves, so one task can wait in `recv` while others send.
+pub struct WsClient {
+    url: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    sink: Mutex<SplitSink<Socket, Message>>,
+    stream: Mutex<SplitStream<Socket>>,
+    reconnect: Option<WsReconnect>,
+    on_reconnect: Option<ReconnectHook>,
+    // Messages held back while reconnecting; `None` while connected
+    offline: std::sync::Mutex<Option<VecDeque<Message>>>,
+    // Either side closed the connection on purpose, or reconnecting gave up
+    closed: AtomicBool,
+}
+
+// The connection is already gone, so there is nothing left to close or read
+fn is_closed(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed)
+}
+
+// The connection was lost rather than refusing this one message
+fn is_dropped(e: &tungstenite::Error) -> bool {
+    is_closed(e) || matches!(e, tungstenite::Error::Io(_) | tungstenite::Error::Protocol(_))
+}
+
+async fn open(url: &str, headers: &[(HeaderName, HeaderValue)]) -> Result<Socket, Box<dyn Error>> {
+    let mut request = url.into_client_request()?;
+    for (name, value) in headers {
+        request.headers_mut().insert(name.clone(), value.clone());
+    }
+    let (socket, _) = tokio_tungstenite::connect_async(request).await?;
+    Ok(socket)
+}
+
+impl WsClient {
+    /// Opens the connection, sending `headers` with the upgrade request.
+    pub async fn connect<K, V>(url: &str, headers: Option<HashMap<K, V>>) -> Result<Self, Box<dyn Error>>
+    where
+        K: Borrow<str>,
+        V: Borrow<str>,
+    {
+        let mut parsed = Vec::new();
+        for (key, value) in headers.into_iter().flatten() {
+            parsed.push((
+                HeaderName::from_bytes(key.borrow().as_bytes())?,
+                HeaderValue::from_str(value.borrow())?,
+            ));
+        }
+        let (sink, stream) = open(url, &parsed).await?.split();
+        Ok(Self {
+            url: url.to_string(),
+            headers: parsed,
+            sink: Mutex::new(sink),
+            stream: Mutex::new(stream),
+            reconnect: None,
+            on_reconnect: None,
+            offline: std::sync::Mutex::new(None),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Reconnects, with the same URL and headers, whenever the connection drops
+    /// without a closing handshake. Reconnection happens in `recv`, so the client
+    /// must be read continuously; messages written just before a drop is noticed
+    /// may be lost.
+    pub fn with_auto_reconnect(mut self, policy: WsReconnect) -> Self {
+        self.set_auto_reconnect(Some(policy));
+        self
+    }
+
+    pub fn set_auto_reconnect(&mut self, policy: Option<WsReconnect>) {
+        self.reconnect = policy;
+    }
+
+    /// Called once each reconnection succeeds, returning the messages to send first
+    /// on the new connection, such as subscriptions. Messages queued while
+    /// disconnected follow them.
+    pub fn with_on_reconnect(mut self, hook: impl Fn() -> Vec<WsMessage> + Send + Sync + 'static) -> Self {
+        self.set_on_reconnect(hook);
+        self
+    }
+
+    pub fn set_on_reconnect(&mut self, hook: impl Fn() -> Vec<WsMessage> + Send + Sync + 'static) {
+        self.on_reconnect = Some(Arc::new(hook));
+    }
+
+    async fn send(&self, message: Message) -> Result<(), Box<dyn Error>> {
+        let Some(message) = self.hold(message)? else {
+            return Ok(());
+        };
+        let mut sink = self.sink.lock().await;
+        match sink.send(message.clone()).await {
+            Err(e) if self.reconnects() && is_dropped(&e) => {
+                // Marked while holding the sink, so the reconnection cannot have
+                // replaced it yet and will pick the message up
+                self.offline.lock().unwrap().get_or_insert_with(VecDeque::new);
+                self.hold(message)?;
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    // Queues or discards `message` while reconnecting, handing it back otherwise
+    fn hold(&self, message: Message) -> Result<Option<Message>, Box<dyn Error>> {
+        let mut offline = self.offline.lock().unwrap();
+        let (Some(queue), Some(policy)) = (offline.as_mut(), &self.reconnect) else {
+            return Ok(Some(message));
+        };
+        match policy.offline {
+            WsOfflineSends::Drop => {}
+            WsOfflineSends::Buffer(limit) if queue.len() < limit => queue.push_back(message),
+            WsOfflineSends::Buffer(limit) => {
+                return Err(format!("reconnecting, with {} messages already queued", limit).into());
+            }
+        }
+        Ok(None)
+    }
+
+    fn reconnects(&self) -> bool {
+        self.reconnect.is_some() && !self.closed.load(Ordering::Acquire)
+    }
+
+    // Opens a new connection, sends what `on_reconnect` returns and the queued
+    // messages on it, and returns its read half
+    async fn reopen(&self, policy: &WsReconnect) -> Result<SplitStream<Socket>, Box<dyn Error>> {
+        self.offline.lock().unwrap().get_or_insert_with(VecDeque::new);
+        let mut attempt = 0;
+        let socket = loop {
+            attempt += 1;
+            tokio::time::sleep(policy.retry.delay(attempt)).await;
+            match open(&self.url, &self.headers).await {
+                Ok(socket) => break socket,
+                Err(e) if attempt >= policy.retry.max_attempts => {
+                    self.closed.store(true, Ordering::Release);
+                    self.offline.lock().unwrap().take();
+                    return Err(format!("reconnecting failed after {} attempts: {}", attempt, e).into());
+                }
+                Err(_) => {}
+            }
+        };
+
+        let (sink, stream) = socket.split();
+        let mut current = self.sink.lock().await;
+        *current = sink;
+        let mut outgoing: Vec<Message> = self.on_reconnect.as_ref()
+            .map(|hook| hook().into_iter().map(WsMessage::into_message).collect())
+            .unwrap_or_default();
+        outgoing.extend(self.offline.lock().unwrap().take().into_iter().flatten());
+        for message in outgoing {
+            current.feed(message).await?;
+        }
+        current.flush().await?;
+        Ok(stream)
+    }
+
+    pub async fn send_text(&self, text: &str) -> Result<(), Box<dyn Error>> {
+        self.send(Message::Text(text.to_string())).await
+    }
+
+    pub async fn send_binary(&self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.send(Message::Binary(data.to_vec())).await
+    }
+
+    /// Sends a ping to keep the connection alive through idle-closing proxies. The
+    /// server's pong is consumed by `recv`.
+    pub async fn ping(&self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.send(Message::Ping(payload.to_vec())).await
+    }
+
+    /// Waits for the next message. Pings from the server are answered and pongs
+    /// skipped. When the server closes the connection its close frame is answered
+    /// and returned as `Close`; every later call returns `None`. With auto-reconnect
+    /// a dropped connection is reopened here, failing once the attempts run out.
+    pub async fn recv(&self) -> Result<Option<WsMessage>, Box<dyn Error>> {
+        let mut stream = self.stream.lock().await;
+        loop {
+            let message = match stream.next().await {
+                Some(Ok(message)) => message,
+                ended => {
+                    if let Some(policy) = self.reconnect.as_ref().filter(|_| self.reconnects()) {
+                        *stream = self.reopen(policy).await?;
+                        continue;
+                    }
+                    match ended {
+                        Some(Err(e)) if !is_closed(&e) => return Err(e.into()),
+                        _ => return Ok(None),
+                    }
+                }
+            };
+            match message {
+                Message::Text(text) => return Ok(Some(WsMessage::Text(text))),
+                Message::Binary(data) => return Ok(Some(WsMessage::Binary(data))),
+                Message::Close(frame) => {
+                    self.closed.store(true, Ordering::Release);
+                    // tungstenite queues the reply to a close frame; flush it so the
+                    // server sees the closing handshake completed
+                    match self.sink.lock().await.flush().await {
+                        Err(e) if !is_closed(&e) => return Err(e.into()),
+                        _ => {}
+                    }
+                    let frame = frame.map(|frame| (u16::from(frame.code), frame.reason.into_owned()));
+                    return Ok(Some(WsMessage::Close(frame)));
+                }
+                // Pongs to server pings are sent by tungstenite as reading continues
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+            }
+        }
+    }
+
+    /// Sends a normal close frame and waits up to 5 seconds for the server's reply,
+    /// discarding messages that arrive meanwhile. Closing an already closed
+    /// connection succeeds.
+    pub async fn close(&self) -> Result<(), Box<dyn Error>> {
+        self.closed.store(true, Ordering::Release);
+        let frame = CloseFrame { code: CloseCode::Normal, reason: "".into() };
+        match self.sink.lock().await.send(Message::Close(Some(frame))).await {
+            Err(e) if !is_closed(&e) => return Err(e.into()),
+            _ => {}
+        }
+        let drain = async {
+            while let Some(message) = self.recv().await? {
+                if let WsMessage::Close(_) = message {
+                    break;
+                }
+            }
+            Ok::<_, Box<dyn Error>>(())
+        };
+        match tokio::time::timeout(CLOSE_TIMEOUT, drain).await {
+            Ok(result) => result,
+            // The server never answered; dropping the client closes the socket
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::net::TcpListener;
+
+    // Runs `session` for each accepted connection, numbered from 0, and returns the URL
+    async fn serve<F, Fut>(session: F) -> String
+    where
+        F: Fn(usize, WebSocketStream<TcpStream>) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for n in 0.. {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let socket = tokio_tungstenite::accept_async(tcp).await.unwrap();
+                tokio::spawn(session(n, socket));
+            }
+        });
+        format!("ws://{}", addr)
+    }
+
+    fn quick_reconnect() -> WsReconnect {
+        WsReconnect {
+            retry: RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(50),
+                ..RetryPolicy::default()
+            },
+            ..WsReconnect::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_server_drops_the_connection() {
+        let url = serve(|n, mut socket| async move {
+            if n == 0 {
+                // Read one message, then drop the connection without a close frame
+                socket.next().await;
+                return;
+            }
+            while let Some(Ok(message)) = socket.next().await {
+                if message.is_text() {
+                    socket.send(message).await.unwrap();
+                }
+            }
+        }).await;
+
+        let reconnects = Arc::new(AtomicUsize::new(0));
+        let counter = reconnects.clone();
+        let ws = WsClient::connect::<&str, &str>(&url, None).await.unwrap()
+            .with_auto_reconnect(quick_reconnect())
+            .with_on_reconnect(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                vec![WsMessage::Text("subscribe".to_string())]
+            });
+
+        ws.send_text("hello").await.unwrap();
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Text("subscribe".to_string())));
+        assert_eq!(reconnects.load(Ordering::SeqCst), 1);
+
+        ws.send_text("after").await.unwrap();
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Text("after".to_string())));
+        ws.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_close_is_not_reconnected() {
+        let url = serve(|_, mut socket| async move {
+            socket.close(None).await.unwrap();
+        }).await;
+        let reconnects = Arc::new(AtomicUsize::new(0));
+        let counter = reconnects.clone();
+        let ws = WsClient::connect::<&str, &str>(&url, None).await.unwrap()
+            .with_auto_reconnect(quick_reconnect())
+            .with_on_reconnect(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Vec::new()
+            });
+
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Close(None)));
+        assert_eq!(ws.recv().await.unwrap(), None);
+        assert_eq!(reconnects.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn echoes_messages_and_keeps_alive_until_the_server_closes() {
+        use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut token = String::new();
+            let read_token = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+                token = request.headers()["x-token"].to_str().unwrap().to_string();
+                Ok(response)
+            };
+            let mut socket = tokio_tungstenite::accept_hdr_async(tcp, read_token).await.unwrap();
+            socket.send(Message::Text(token)).await.unwrap();
+            socket.send(Message::Ping(b"keepalive".to_vec())).await.unwrap();
+            while let Some(Ok(message)) = socket.next().await {
+                match message {
+                    Message::Text(text) if text == "bye" => {
+                        let frame = CloseFrame { code: CloseCode::from(4000), reason: "done".into() };
+                        socket.close(Some(frame)).await.unwrap();
+                    }
+                    Message::Text(_) | Message::Binary(_) => socket.send(message).await.unwrap(),
+                    // Report what arrived as text, since the client never hands these out
+                    Message::Ping(payload) => {
+                        let text = format!("ping {}", String::from_utf8(payload).unwrap());
+                        socket.send(Message::Text(text)).await.unwrap();
+                    }
+                    Message::Pong(payload) => {
+                        let text = format!("pong {}", String::from_utf8(payload).unwrap());
+                        socket.send(Message::Text(text)).await.unwrap();
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let headers = HashMap::from([("X-Token", "secret")]);
+        let ws = WsClient::connect(&url, Some(headers)).await.unwrap();
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Text("secret".to_string())));
+
+        // The server's ping is answered while waiting for the echo
+        ws.send_text("hello").await.unwrap();
+        let mut replies = vec![ws.recv().await.unwrap(), ws.recv().await.unwrap()];
+        replies.sort_by_key(|reply| format!("{:?}", reply));
+        assert_eq!(replies, [
+            Some(WsMessage::Text("hello".to_string())),
+            Some(WsMessage::Text("pong keepalive".to_string())),
+        ]);
+
+        ws.send_binary(&[0, 1, 255]).await.unwrap();
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Binary(vec![0, 1, 255])));
+
+        // The server's pong is skipped
+        ws.ping(b"are you there").await.unwrap();
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Text("ping are you there".to_string())));
+
+        ws.send_text("bye").await.unwrap();
+        assert_eq!(ws.recv().await.unwrap(), Some(WsMessage::Close(Some((4000, "done".to_string())))));
+        assert_eq!(ws.recv().await.unwrap(), None);
+        ws.close().await.unwrap();
+    }
+}
@@ -6,6 +6,14 @@ pub mod core {
     pub mod zip;
     pub mod lua;
     pub mod qjs;
+    pub mod engine;
+    pub mod queue;
+    pub mod cassette;
+    pub mod cookie;
+    pub mod http_cache;
+    pub mod session;
+    pub mod sse;
+    pub mod ws;
 }
 
 pub mod c {
@@ -17,4 +25,10 @@ pub mod c {
     pub mod zip;
     pub mod lua;
     pub mod qjs;
+    pub mod engine;
+    pub mod queue;
+    pub mod session;
+    pub mod ws;
 }
+#[cfg(test)]
+mod test_util;
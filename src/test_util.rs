@@ -0,0 +1,342 @@
+//! Helpers shared by the unit tests: temporary files and a throwaway HTTP/1.1 stub server.
+
+// Not every helper or field is used by every test build
+#![allow(dead_code)]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::core::kv::KV;
+
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// A path in the temp directory, unique to this test run, removed on drop.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let n = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+        Self(std::env::temp_dir().join(format!("dynrs-test-{}-{}-{}", std::process::id(), n, name)))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A fresh KV store; keep the path alive for as long as the store is used.
+pub fn temp_kv() -> (KV, TempPath) {
+    let path = TempPath::new("kv.redb");
+    (KV::open(&*path).unwrap(), path)
+}
+
+/// A request as received by `serve`, with a chunked body already reassembled.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub chunked: bool,
+    pub peer: SocketAddr,
+}
+
+impl Request {
+    /// The first value of the header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A complete response with a `Content-Length` for `body`.
+pub fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} Stub\r\ncontent-length: {}\r\n", status, body.len());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+fn read_request(reader: &mut impl BufRead, peer: SocketAddr) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request { method, target, headers, body: Vec::new(), chunked: false, peer };
+    if let Some(length) = request.header("content-length") {
+        let mut body = vec![0; length.parse().ok()?];
+        reader.read_exact(&mut body).ok()?;
+        request.body = body;
+    } else if request.header("transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        request.chunked = true;
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).ok()?;
+            let size = usize::from_str_radix(size.trim().split(';').next()?, 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).ok()?;
+            if size == 0 {
+                break;
+            }
+            request.body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    Some(request)
+}
+
+/// Serves HTTP/1.1 on a loopback port until the test process exits, answering each
+/// request with the raw bytes `handler` returns. Connections are kept alive unless
+/// the response says `connection: close`. Returns the base URL, e.g. `http://127.0.0.1:1234`.
+pub fn serve(handler: impl Fn(&Request) -> Vec<u8> + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let handler = handler.clone();
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().unwrap();
+                let mut writer = stream.try_clone().unwrap();
+                let mut reader = BufReader::new(stream);
+                while let Some(request) = read_request(&mut reader, peer) {
+                    let response = handler(&request);
+                    if writer.write_all(&response).is_err() {
+                        return;
+                    }
+                    let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(response.len());
+                    if String::from_utf8_lossy(&response[..head_end]).to_ascii_lowercase().contains("connection: close") {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// Accepts connections on a loopback port and hands each one, with the request
+/// already read, to `handler`, which writes whatever it likes. For slow, truncated
+/// or otherwise malformed responses.
+pub fn serve_raw(handler: impl Fn(Request, TcpStream) + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let handler = handler.clone();
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().unwrap();
+                let writer = stream.try_clone().unwrap();
+                let mut reader = BufReader::new(stream);
+                if let Some(request) = read_request(&mut reader, peer) {
+                    handler(request, writer);
+                }
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// The name the certificate of `serve_tls` is issued to, by the CA in `TEST_CA_PATH`.
+pub const TLS_HOST: &str = "staging.dynrs.test";
+pub const TEST_CA_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/ca.pem");
+
+/// Serves HTTPS like `serve`, one request per connection, with a certificate for
+/// `TLS_HOST`. Returns the loopback address, to reach through a host override.
+pub fn serve_tls(handler: impl Fn(&Request) -> Vec<u8> + Send + Sync + 'static) -> SocketAddr {
+    let identity = native_tls::Identity::from_pkcs8(
+        include_bytes!("../testdata/tls/server.pem"),
+        include_bytes!("../testdata/tls/server.key"),
+    ).unwrap();
+    let acceptor = Arc::new(native_tls::TlsAcceptor::new(identity).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (acceptor, handler) = (acceptor.clone(), handler.clone());
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().unwrap();
+                // Clients that reject the certificate end the handshake
+                let Ok(stream) = acceptor.accept(stream) else { return };
+                let mut reader = BufReader::new(stream);
+                if let Some(request) = read_request(&mut reader, peer) {
+                    let mut response = handler(&request);
+                    let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(0);
+                    response.splice(head_end..head_end, *b"\r\nconnection: close");
+                    let stream = reader.get_mut();
+                    let _ = stream.write_all(&response);
+                    let _ = stream.shutdown();
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// Serves HTTPS like `serve_tls`, but completes the handshake only with a client
+/// certificate issued by the CA in `TEST_CA_PATH`. `handler` also gets the common
+/// name of that certificate.
+#[cfg(target_os = "linux")]
+pub fn serve_mtls(handler: impl Fn(&Request, &str) -> Vec<u8> + Send + Sync + 'static) -> SocketAddr {
+    use openssl::nid::Nid;
+    use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+    let tls = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls");
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_certificate_chain_file(format!("{}/server.pem", tls)).unwrap();
+    acceptor.set_private_key_file(format!("{}/server.key", tls), SslFiletype::PEM).unwrap();
+    acceptor.set_ca_file(TEST_CA_PATH).unwrap();
+    acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let acceptor = Arc::new(acceptor.build());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (acceptor, handler) = (acceptor.clone(), handler.clone());
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().unwrap();
+                let Ok(stream) = acceptor.accept(stream) else { return };
+                let common_name = stream.ssl().peer_certificate()
+                    .and_then(|cert| cert.subject_name().entries_by_nid(Nid::COMMONNAME).next()
+                        .map(|entry| String::from_utf8_lossy(entry.data().as_slice()).into_owned()))
+                    .unwrap_or_default();
+                let mut reader = BufReader::new(stream);
+                if let Some(request) = read_request(&mut reader, peer) {
+                    let mut response = handler(&request, &common_name);
+                    let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(0);
+                    response.splice(head_end..head_end, *b"\r\nconnection: close");
+                    let stream = reader.get_mut();
+                    let _ = stream.write_all(&response);
+                    let _ = stream.shutdown();
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// A loopback address nothing listens on.
+pub fn closed_port_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    format!("http://{}", addr)
+}
+
+thread_local! {
+    static LARGEST_ALLOCATION: Cell<usize> = const { Cell::new(0) };
+    // Bytes allocated minus bytes freed on this thread, and the most it has reached
+    static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+    static PEAK_LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+/// The system allocator, recording the largest single allocation made on each
+/// thread and the bytes each thread holds, so tests can check that a large value
+/// is never buffered whole and that frees give back what was allocated.
+struct TrackingAlloc;
+
+unsafe impl GlobalAlloc for TrackingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        record_free(layout.size());
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_free(layout.size());
+        record_allocation(new_size);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+// The slots are gone while the thread shuts down, hence `try_with`
+fn record_allocation(size: usize) {
+    let _ = LARGEST_ALLOCATION.try_with(|largest| largest.set(largest.get().max(size)));
+    let _ = LIVE_BYTES.try_with(|live| {
+        live.set(live.get() + size as isize);
+        let _ = PEAK_LIVE_BYTES.try_with(|peak| peak.set(peak.get().max(live.get())));
+    });
+}
+
+fn record_free(size: usize) {
+    let _ = LIVE_BYTES.try_with(|live| live.set(live.get() - size as isize));
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAlloc = TrackingAlloc;
+
+/// Runs `f` and returns its result with the largest single allocation it made on
+/// this thread, in bytes.
+pub fn largest_allocation<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let saved = LARGEST_ALLOCATION.with(|largest| largest.replace(0));
+    let result = f();
+    let size = LARGEST_ALLOCATION.with(|largest| largest.replace(saved.max(largest.get())));
+    (result, size)
+}
+
+/// Bytes allocated on this thread and not yet freed; memory freed by another
+/// thread than the one that allocated it skews both counts.
+pub fn live_bytes() -> isize {
+    LIVE_BYTES.with(Cell::get)
+}
+
+/// Runs `f` and returns its result with the most bytes it held allocated at once
+/// on this thread, on top of what was allocated before.
+pub fn peak_live_bytes<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let start = live_bytes();
+    let saved = PEAK_LIVE_BYTES.with(|peak| peak.replace(start));
+    let result = f();
+    let peak = PEAK_LIVE_BYTES.with(|peak| peak.replace(saved.max(peak.get())));
+    (result, (peak - start).max(0) as usize)
+}